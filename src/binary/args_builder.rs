@@ -31,7 +31,7 @@ use crate::config::prop::{ProjectArgs, ProjectConfig, SourceKeyMode};
 use crate::lib::SoftError;
 use crate::lib::SoftError::AppError;
 use crate::log::debug;
-use crate::utils::log::warn;
+use crate::utils::log::{add_sensitive, warn};
 use crate::utils::string;
 
 #[derive(Debug)]
@@ -54,25 +54,11 @@ pub struct BinaryArg {
 默认情况下，环境变量配置优于配置文件下配置，
 
  **/
-pub fn load_context(config: &ProjectConfig) -> Result<BinaryContext, SoftError> {
+pub fn load_variables(config: &ProjectConfig) -> Result<HashMap<String, String>, SoftError> {
     let mut args_container: HashMap<String, String> = HashMap::new(); // 变量容器
-    let var_replace = String::from("{{item}}");
-    let attrs: HashMap<String, String> = config
-        .attach
-        .iter()
-        .map(|e| (var_replace.replace("item", e.0), e.1.to_owned()))
-        .collect(); // 项目所有的变量
+    let attrs = attach_vars(config); // 项目所有的变量
     for conf in &config.path {
-        let res = if conf.starts_with("file://") {
-            // 加载本地文件
-            load_form_local(&mut args_container, &conf, false)
-        } else if conf.starts_with("http://") || conf.starts_with("https://") {
-            // 加载网络配置
-            load_form_remote(&mut args_container, &conf, false)
-        } else {
-            // 默认加载本地配置
-            load_form_local(&mut args_container, &format!("file://{}", conf), false)
-        };
+        let res = load_path(&mut args_container, conf);
         if let Err(e) = res {
             warn(format!("无法从'{}'位置加载配置，因为{}.", &conf, e))
         }
@@ -96,6 +82,7 @@ pub fn load_context(config: &ProjectConfig) -> Result<BinaryContext, SoftError>
     for (key, value) in env_clone {
         args_container.insert(key, value);
     }
+    register_sensitive(&args_container, &config.sensitive_keys);
     // 添加附加的变量
     for alias in &config.config_alias {
         let data = alias
@@ -106,6 +93,9 @@ pub fn load_context(config: &ProjectConfig) -> Result<BinaryContext, SoftError>
             .map(|e| e.unwrap());
         if let Some(data) = data {
             let key = alias.key.to_owned();
+            if alias.sensitive || is_sensitive_key(&key, &config.sensitive_keys) {
+                add_sensitive(&data);
+            }
             if alias.over || args_container.contains_key(&key).not() {
                 debug(format!("配置 '{}' 已填充 '{}' 内容", &key, &data));
                 args_container.insert(key, data);
@@ -122,13 +112,38 @@ pub fn load_context(config: &ProjectConfig) -> Result<BinaryContext, SoftError>
     for args_item in args_container.iter_mut() {
         string::replace_all_str_from_map(args_item.1, &attrs);
     } // 遍历替换参数内容变量
+    register_sensitive(&args_container, &config.sensitive_keys);
+    Ok(args_container)
+}
+
+fn attach_vars(config: &ProjectConfig) -> HashMap<String, String> {
+    let var_replace = String::from("{{item}}");
+    config
+        .attach
+        .iter()
+        .map(|e| (var_replace.replace("item", e.0), e.1.to_owned()))
+        .collect()
+}
+
+/**
+根据程序的参数配置，从已加载的变量中生成启动参数与环境变量
+ **/
+pub fn load_context(
+    config: &ProjectConfig,
+    args_container: &HashMap<String, String>,
+    program_args: &[ProjectArgs],
+) -> Result<BinaryContext, SoftError> {
+    let var_replace = String::from("{{item}}");
+    let attrs = attach_vars(config);
     let mut args: Vec<BinaryArg> = vec![];
-    for arg in &config.args {
-        if let Some(arg) = get_then_check_arg(&arg, &args_container)? {
+    for arg in program_args {
+        let sensitive = arg.sensitive || is_sensitive_key(&arg.key, &config.sensitive_keys);
+        if let Some(arg) = get_then_check_arg(arg, args_container, sensitive)? {
             args.push(arg);
         }
     } // 装入变量并检查合法性
-    let mut out_envs: HashMap<String, String> = env::vars().collect();
+      // 仅包含参数中的环境变量，继承的环境变量由执行环境决定
+    let mut out_envs: HashMap<String, String> = HashMap::new();
     let mut out_args: Vec<String> = vec![];
     let mut script_vars: HashMap<String, String> = attrs.clone();
 
@@ -148,7 +163,7 @@ pub fn load_context(config: &ProjectConfig) -> Result<BinaryContext, SoftError>
         }
     }
     for (k, v) in args_container {
-        script_vars.insert(var_replace.replace("item", &k).to_string(), v);
+        script_vars.insert(var_replace.replace("item", k).to_string(), v.to_string());
     }
     Ok(BinaryContext {
         args: out_args,
@@ -157,9 +172,50 @@ pub fn load_context(config: &ProjectConfig) -> Result<BinaryContext, SoftError>
     })
 }
 
+/// 从 `path` 中的单个位置加载配置
+pub fn load_path(container: &mut HashMap<String, String>, conf: &String) -> Result<(), SoftError> {
+    if conf.starts_with("file://") {
+        // 加载本地文件
+        load_form_local(container, conf, false)
+    } else if conf.starts_with("http://") || conf.starts_with("https://") {
+        // 加载网络配置
+        load_form_remote(container, conf, false)
+    } else {
+        // 默认加载本地配置
+        load_form_local(container, &format!("file://{}", conf), false)
+    }
+}
+
+/// `path` 中的本地配置文件
+pub fn local_sources(config: &ProjectConfig) -> Vec<PathBuf> {
+    config
+        .path
+        .iter()
+        .filter(|e| e.starts_with("http://").not() && e.starts_with("https://").not())
+        .map(|e| PathBuf::from(e.trim_start_matches("file://").trim()))
+        .map(|e| fs::canonicalize(&e).unwrap_or(e))
+        .collect()
+}
+
+/// 判断配置 key 是否命中敏感规则（忽略大小写的包含匹配）
+pub fn is_sensitive_key(key: &str, patterns: &[String]) -> bool {
+    let key = key.to_lowercase();
+    patterns
+        .iter()
+        .filter(|e| e.trim().is_empty().not())
+        .any(|e| key.contains(&e.trim().to_lowercase()))
+}
+
+fn register_sensitive(vars: &HashMap<String, String>, patterns: &[String]) {
+    vars.iter()
+        .filter(|(key, _)| is_sensitive_key(key, patterns))
+        .for_each(|(_, value)| add_sensitive(value));
+}
+
 fn get_then_check_arg(
     args: &ProjectArgs,
     vars: &HashMap<String, String>,
+    sensitive: bool,
 ) -> Result<Option<BinaryArg>, SoftError> {
     let regex_str = args.valid_regex.trim();
 
@@ -172,6 +228,9 @@ fn get_then_check_arg(
             continue;
         }
         let filled_arg_format = filled_arg_format.unwrap();
+        if sensitive {
+            add_sensitive(&filled_arg_format);
+        }
         if dist_value_regex.is_match(&filled_arg_format).not() {
             let message = args
                 .valid_message
@@ -194,6 +253,15 @@ fn get_then_check_arg(
     return Ok(None);
 }
 
+#[test]
+fn is_sensitive_key_test() {
+    let patterns = vec!["password".to_string(), "Token".to_string(), "".to_string()];
+    assert!(is_sensitive_key("redis.password", &patterns));
+    assert!(is_sensitive_key("GITHUB_TOKEN", &patterns));
+    assert!(is_sensitive_key("--api-token", &patterns));
+    assert!(!is_sensitive_key("redis.port", &patterns));
+}
+
 fn load_form_local(
     container: &mut HashMap<String, String>,
     config_path: &String,
@@ -21,31 +21,37 @@
  */
 
 pub mod args;
+pub mod compose;
+pub mod lint;
 pub mod prop;
+pub mod schema;
 
 pub mod project_conf {
     use std::collections::HashMap;
+    use std::env;
     use std::fs::canonicalize;
     use std::io::{Error as IOError, ErrorKind};
     use std::ops::Not;
     use std::path::{Path, PathBuf};
     use std::str::FromStr;
-    use std::{env, fs};
 
     use is_executable::IsExecutable;
 
-    use crate::config::prop::ProjectConfig;
+    use crate::config::compose::{apply_profiles, load_config_tree, parse_config};
+    use crate::config::prop::{ProgramInfo, ProjectConfig};
     use crate::lib::SoftError;
     use crate::utils;
 
     /**
-    加载配置文件
+    加载配置文件，`profiles` 为按顺序启用的配置档案，同时返回 `extends` / `include` 涉及的全部配置文件
      */
     pub fn load_info(
         config_path: &str,
         attrs: &HashMap<String, String>,
-    ) -> Result<ProjectConfig, SoftError> {
+        profiles: &[String],
+    ) -> Result<(ProjectConfig, Vec<PathBuf>), SoftError> {
         let mut attrs = attrs.clone();
+        attrs.insert("profile".to_string(), profiles.join(","));
         let path = canonicalize(Path::new(config_path)).map_err(|_| {
             SoftError::AppError(format!("配置文件 {} 不存在.", config_path).to_string())
         })?;
@@ -55,71 +61,169 @@ pub mod project_conf {
                 format!("配置文件 {} 不存在.", config_path).to_string(),
             ));
         };
-        let mut config_data_str = fs::read_to_string(_config_path)?;
         let _static_var = String::from("{{item}}");
-        utils::string::replace_all_str(
-            &mut config_data_str,
+        let (config_tree, files) = load_config_tree(
+            _config_path,
             &attrs
                 .iter()
                 .map(|e| (_static_var.replace("item", e.0), e.1.to_string()))
                 .collect(),
-        );
+        )?;
+        let config_tree = apply_profiles(config_tree, profiles)?;
 
-        let mut result: ProjectConfig = serde_yaml::from_str(&config_data_str)
-            .map_err(|e| IOError::new(ErrorKind::Other, e.to_string()))?;
+        let mut result = parse_config(&config_tree)?;
         result.attach.iter().for_each(|it| {
             (&mut attrs)
                 .entry(it.0.to_owned())
                 .or_insert(it.1.to_owned());
         });
 
+        result.programs = result.all_programs();
+        result.project = None;
+        result.args = vec![];
+        if result.programs.is_empty() {
+            return Err(SoftError::AppError(
+                "配置文件中未定义任何程序，请配置 project 或 programs.".to_string(),
+            ));
+        }
+        start_order(&result.programs)?;
+        for (index, program) in result.programs.iter_mut().enumerate() {
+            let binary_path = resolve_binary(_config_path, &program.project.binary)?;
+            if index == 0 {
+                attrs.insert("binary.location".to_string(), binary_path.to_string());
+            }
+            program.project.binary = binary_path;
+        }
+        result.attach = attrs.clone();
+        let mut config_data_str = serde_yaml::to_string(&result).unwrap();
+        utils::string::replace_all_str(
+            &mut config_data_str,
+            &result
+                .attach
+                .iter()
+                .map(|e| (_static_var.replace("item", e.0), e.1.to_string()))
+                .collect(),
+        );
+        let result: ProjectConfig = serde_yaml::from_str(&config_data_str)
+            .map_err(|e| IOError::new(ErrorKind::Other, e.to_string()))?;
+        Ok((result, files))
+    }
+
+    /**
+    查找可执行文件，依次尝试原始路径、相对配置文件与相对当前目录的位置
+     */
+    fn resolve_binary(config_path: &Path, binary: &str) -> Result<String, SoftError> {
+        let mut binary = binary.to_string();
         let binary_paths = vec![
-            PathBuf::from_str(&result.project.binary)?,
+            PathBuf::from_str(&binary)?,
             PathBuf::from(format!(
                 "{}{}",
-                canonicalize(_config_path.parent().unwrap())?
+                canonicalize(config_path.parent().unwrap())?
                     .to_str()
                     .unwrap(),
-                &result.project.binary
+                &binary
             )),
             PathBuf::from(format!(
                 "{}{}",
                 canonicalize(env::current_dir().unwrap())?.to_str().unwrap(),
-                &result.project.binary
+                &binary
             )),
         ];
         for binary_path in binary_paths {
             if binary_path.is_file() {
                 // 如果文件存在
                 let binary_path = canonicalize(&binary_path).unwrap();
-                let binary_path = binary_path.to_str().unwrap();
-                attrs.insert("binary.location".to_string(), binary_path.to_string());
-                result.project.binary = binary_path.to_string();
+                binary = binary_path.to_str().unwrap().to_string();
                 break;
             }
         }
-        if PathBuf::from(&result.project.binary).is_file().not() {
+        if PathBuf::from(&binary).is_file().not() {
             return Err(SoftError::AppError(
-                format!("可执行文件 {} 不存在.", &result.project.binary).to_string(),
+                format!("可执行文件 {} 不存在.", &binary).to_string(),
             ));
         }
-        if Path::new(&result.project.binary).is_executable().not() {
+        if Path::new(&binary).is_executable().not() {
             return Err(SoftError::AppError(
-                format!("可执行文件 {} 无运行权限.", &result.project.binary).to_string(),
+                format!("可执行文件 {} 无运行权限.", &binary).to_string(),
             ));
         }
-        result.attach = attrs.clone();
-        config_data_str = serde_yaml::to_string(&result).unwrap();
-        utils::string::replace_all_str(
-            &mut config_data_str,
-            &result
-                .attach
-                .iter()
-                .map(|e| (_static_var.replace("item", e.0), e.1.to_string()))
-                .collect(),
-        );
-        let result: ProjectConfig = serde_yaml::from_str(&config_data_str)
-            .map_err(|e| IOError::new(ErrorKind::Other, e.to_string()))?;
-        Ok(result)
+        Ok(binary)
+    }
+
+    /**
+    按 `depends_on` 计算程序的启动顺序，被依赖的程序先启动，其余按声明顺序启动
+     */
+    pub fn start_order(programs: &[ProgramInfo]) -> Result<Vec<usize>, SoftError> {
+        let names: Vec<&str> = programs.iter().map(|e| e.project.name.as_str()).collect();
+        for (index, program) in programs.iter().enumerate() {
+            let name = &program.project.name;
+            if names[..index].contains(&name.as_str()) {
+                return Err(SoftError::AppError(format!("程序名称 {} 重复.", name)));
+            }
+            for depend in &program.depends_on {
+                if names.contains(&depend.as_str()).not() {
+                    return Err(SoftError::AppError(format!(
+                        "程序 {} 依赖的程序 {} 不存在.",
+                        name, depend
+                    )));
+                }
+            }
+        }
+        let mut order: Vec<usize> = vec![];
+        while order.len() < programs.len() {
+            let next = (0..programs.len()).find(|index| {
+                order.contains(index).not()
+                    && programs[*index]
+                        .depends_on
+                        .iter()
+                        .all(|depend| order.iter().any(|e| names[*e] == depend))
+            });
+            match next {
+                Some(index) => order.push(index),
+                None => {
+                    let rest: Vec<&str> = (0..programs.len())
+                        .filter(|e| order.contains(e).not())
+                        .map(|e| names[e])
+                        .collect();
+                    return Err(SoftError::AppError(format!(
+                        "程序之间存在循环依赖: {}",
+                        rest.join(", ")
+                    )));
+                }
+            }
+        }
+        Ok(order)
+    }
+
+    #[test]
+    fn start_order_test() {
+        let programs: Vec<ProgramInfo> = serde_yaml::from_str(
+            r#"
+- name: app
+  binary: app.sh
+  depends_on: [db, cache]
+- name: db
+  binary: db.sh
+- name: cache
+  binary: cache.sh
+  depends_on: [db]
+- name: shipper
+  binary: shipper.sh
+"#,
+        )
+        .unwrap();
+        assert_eq!(start_order(&programs).unwrap(), vec![1, 2, 0, 3]);
+        let programs: Vec<ProgramInfo> = serde_yaml::from_str(
+            r#"
+- name: a
+  binary: a.sh
+  depends_on: [b]
+- name: b
+  binary: b.sh
+  depends_on: [a]
+"#,
+        )
+        .unwrap();
+        assert!(start_order(&programs).is_err());
     }
 }
@@ -26,7 +26,7 @@ pub mod soft_args {
     use std::ops::Not;
     use std::path::PathBuf;
 
-    use clap::Parser;
+    use clap::{Parser, Subcommand};
 
     use crate::config::prop::LoggerLevel;
     use crate::log_default;
@@ -40,11 +40,35 @@ pub mod soft_args {
         /// 添加内部替换的变量
         #[clap(short = 'a', long = "--attach")]
         pub variable: Vec<String>,
-        /// 配置控制台输出的日志级别
-        #[clap(short = 'l', long = "--level", default_value_t = LoggerLevel::INFO)]
-        pub console_log_level: LoggerLevel,
+        /// 配置控制台输出的日志级别，指定时覆盖配置文件中的 log.console.level
+        #[clap(short = 'l', long = "--level")]
+        pub console_log_level: Option<LoggerLevel>,
+        /// 启用的配置档案，可重复指定或以逗号分隔，未指定时读取环境变量 ARGS_PROFILE
+        #[clap(short = 'p', long = "--profile")]
+        pub profiles: Vec<String>,
+        /// 仅输出解析后的启动命令与环境变量，不启动程序
+        #[clap(long = "--dry-run")]
+        pub dry_run: bool,
+        /// 以 init 模式运行：回收全部子进程、转发信号并以程序的退出码退出，PID 为 1 时自动启用
+        #[clap(long = "--init")]
+        pub init: bool,
+        #[clap(subcommand)]
+        pub command: Option<SoftCommand>,
     }
 
+    #[derive(Subcommand, Debug, Clone)]
+    pub enum SoftCommand {
+        /// 输出配置文件的 JSON Schema
+        SCHEMA,
+        /// 检查配置文件中无效或危险的配置项
+        LINT,
+        /// 解析参数并执行启动前脚本后，以可执行文件替换当前进程，不进行监控
+        EXEC,
+    }
+
+    /// 指定配置档案的环境变量
+    pub const PROFILE_ENV: &str = "ARGS_PROFILE";
+
     fn about() -> &'static str {
         include_str!("about.txt")
     }
@@ -52,8 +76,12 @@ pub mod soft_args {
     #[derive(Debug)]
     pub struct SoftArgs {
         pub config_path: String,
-        pub log_level: LoggerLevel,
+        pub log_level: Option<LoggerLevel>,
         pub variable: HashMap<String, String>,
+        pub profiles: Vec<String>,
+        pub dry_run: bool,
+        pub init: bool,
+        pub command: Option<SoftCommand>,
     }
 
     impl SoftArgs {
@@ -88,11 +116,24 @@ pub mod soft_args {
                     .unwrap()
                     .to_string(),
             );
-            log_default(args.console_log_level);
+            let profiles = Some(args.profiles)
+                .filter(|e| e.is_empty().not())
+                .or_else(|| env::var(PROFILE_ENV).ok().map(|e| vec![e]))
+                .unwrap_or_default()
+                .iter()
+                .flat_map(|e| e.split(','))
+                .map(|e| e.trim().to_string())
+                .filter(|e| e.is_empty().not())
+                .collect();
+            log_default(args.console_log_level.unwrap_or(LoggerLevel::INFO));
             SoftArgs {
                 log_level: args.console_log_level,
                 config_path: args.config_path,
                 variable: attach,
+                profiles,
+                dry_run: args.dry_run,
+                init: args.init,
+                command: args.command,
             }
         }
     }
@@ -0,0 +1,359 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! 配置文件组合
+//!
+//! 配置文件可通过 `extends:` 继承一个基础配置，通过 `include:` 引入多个配置，
+//! 路径均相对于声明它的配置文件。合并顺序为 `extends` -> `include`(按声明顺序) -> 当前文件，
+//! 后合并的内容覆盖先合并的内容：
+//!
+//! - 映射(map)逐 key 深度合并；
+//! - `args`、`config_alias` 列表按 `key` 字段合并，相同 `key` 的条目被替换，其余追加；
+//! - `path` 列表追加，已存在的路径不会重复添加；
+//! - 其他列表与标量直接替换。
+
+use std::fs;
+use std::fs::canonicalize;
+use std::mem;
+use std::ops::Not;
+use std::path::{Path, PathBuf};
+
+use serde_yaml::{Mapping, Value};
+
+use crate::config::prop::ProjectConfig;
+use crate::lib::SoftError;
+use crate::utils;
+
+/// 按 `key` 字段合并的列表
+const KEYED_LISTS: [&str; 2] = ["args", "config_alias"];
+/// 追加合并的列表
+const APPEND_LISTS: [&str; 1] = ["path"];
+
+/**
+加载配置文件及其 `extends` / `include` 引用的所有配置，返回合并后的配置与涉及的全部文件
+ */
+pub fn load_config_tree(
+    config_path: &Path,
+    attrs: &Vec<(String, String)>,
+) -> Result<(Value, Vec<PathBuf>), SoftError> {
+    let mut stack = vec![];
+    let mut files = vec![];
+    let value = load_file(config_path, attrs, &mut stack, &mut files)?;
+    Ok((value, files))
+}
+
+fn load_file(
+    path: &Path,
+    attrs: &Vec<(String, String)>,
+    stack: &mut Vec<PathBuf>,
+    files: &mut Vec<PathBuf>,
+) -> Result<Value, SoftError> {
+    let path = canonicalize(path).map_err(|_| {
+        SoftError::AppError(format!("配置文件 {} 不存在.", path.to_str().unwrap_or("")))
+    })?;
+    if path.is_file().not() {
+        return Err(SoftError::AppError(format!(
+            "配置文件 {} 不存在.",
+            path.to_str().unwrap_or("")
+        )));
+    }
+    if stack.contains(&path) {
+        let chain: Vec<&str> = stack
+            .iter()
+            .chain(Some(&path))
+            .map(|e| e.to_str().unwrap_or(""))
+            .collect();
+        return Err(SoftError::AppError(format!(
+            "配置文件存在循环引用: {}",
+            chain.join(" -> ")
+        )));
+    }
+    if files.contains(&path).not() {
+        files.push(path.clone());
+    }
+    let mut data = fs::read_to_string(&path)?;
+    utils::string::replace_all_str(&mut data, attrs);
+    let mut value: Value = serde_yaml::from_str(&data).map_err(|e| {
+        SoftError::AppError(format!(
+            "配置文件 {} 格式错误: {}",
+            path.to_str().unwrap_or(""),
+            e
+        ))
+    })?;
+    if value.is_null() {
+        value = Value::Mapping(Mapping::new());
+    }
+    let mapping = value.as_mapping_mut().ok_or_else(|| {
+        SoftError::AppError(format!(
+            "配置文件 {} 的根节点必须为映射.",
+            path.to_str().unwrap_or("")
+        ))
+    })?;
+    let extends = mapping.remove("extends");
+    let include = mapping.remove("include");
+    let parent = path.parent().map(|e| e.to_path_buf()).unwrap_or_default();
+    stack.push(path.clone());
+    let mut base = Value::Mapping(Mapping::new());
+    for extends in extends
+        .iter()
+        .filter(|e| e.is_null().not() && e.as_str() != Some(""))
+    {
+        let extends = extends.as_str().ok_or_else(|| {
+            SoftError::AppError(format!(
+                "配置文件 {} 的 extends 必须为文件路径.",
+                path.to_str().unwrap_or("")
+            ))
+        })?;
+        base = load_file(&parent.join(extends), attrs, stack, files)?;
+    }
+    let include: Vec<Value> = match include {
+        None | Some(Value::Null) => vec![],
+        Some(Value::String(item)) => vec![Value::String(item)],
+        Some(Value::Sequence(items)) => items,
+        Some(_) => {
+            return Err(SoftError::AppError(format!(
+                "配置文件 {} 的 include 必须为文件路径列表.",
+                path.to_str().unwrap_or("")
+            )))
+        }
+    };
+    for item in include {
+        let item = item.as_str().ok_or_else(|| {
+            SoftError::AppError(format!(
+                "配置文件 {} 的 include 必须为文件路径列表.",
+                path.to_str().unwrap_or("")
+            ))
+        })?;
+        let included = load_file(&parent.join(item), attrs, stack, files)?;
+        base = merge(base, included);
+    }
+    stack.pop();
+    Ok(merge(base, value))
+}
+
+/**
+将组合后的配置转换为配置结构
+ */
+pub fn parse_config(config: &Value) -> Result<ProjectConfig, SoftError> {
+    // 经由文本反序列化，保持与单文件配置一致的标量转换规则
+    let data = serde_yaml::to_string(config).map_err(|e| SoftError::AppError(e.to_string()))?;
+    serde_yaml::from_str(&data).map_err(|e| SoftError::AppError(e.to_string()))
+}
+
+/**
+按顺序将启用的配置档案 (`profiles`) 覆盖到配置上，合并规则与 `include` 相同
+ */
+pub fn apply_profiles(mut config: Value, profiles: &[String]) -> Result<Value, SoftError> {
+    let defined = config
+        .as_mapping_mut()
+        .and_then(|e| e.remove("profiles"))
+        .unwrap_or(Value::Null);
+    for name in profiles {
+        let overlay = defined.get(name.as_str()).ok_or_else(|| {
+            SoftError::AppError(format!("配置档案 {} 未定义，请检查 profiles 配置.", name))
+        })?;
+        config = merge(config, overlay.clone());
+    }
+    Ok(config)
+}
+
+/**
+合并两个配置，`overlay` 中的内容优先
+ */
+pub fn merge(base: Value, overlay: Value) -> Value {
+    merge_field("", base, overlay)
+}
+
+fn merge_field(field: &str, base: Value, overlay: Value) -> Value {
+    match (base, overlay) {
+        (Value::Mapping(mut base), Value::Mapping(overlay)) => {
+            for (key, value) in overlay {
+                let name = key.as_str().unwrap_or("").to_string();
+                match base.get_mut(&key) {
+                    Some(old) => *old = merge_field(&name, mem::take(old), value),
+                    None => {
+                        base.insert(key, value);
+                    }
+                }
+            }
+            Value::Mapping(base)
+        }
+        (Value::Sequence(mut base), Value::Sequence(overlay)) => {
+            if KEYED_LISTS.contains(&field) {
+                for item in overlay {
+                    let position = item
+                        .get("key")
+                        .and_then(|key| base.iter().position(|e| e.get("key") == Some(key)));
+                    match position {
+                        Some(index) => base[index] = item,
+                        None => base.push(item),
+                    }
+                }
+                Value::Sequence(base)
+            } else if APPEND_LISTS.contains(&field) {
+                for item in overlay {
+                    if base.contains(&item).not() {
+                        base.push(item);
+                    }
+                }
+                Value::Sequence(base)
+            } else {
+                Value::Sequence(overlay)
+            }
+        }
+        (_, overlay) => overlay,
+    }
+}
+
+#[test]
+fn merge_test() {
+    let base: Value = serde_yaml::from_str(
+        r#"
+project:
+  name: base
+  check_health:
+    delay: 3
+    interval: 2
+args:
+  - key: --a
+    expr: ['1']
+  - key: --b
+    expr: ['2']
+path:
+  - /etc/a
+log:
+  file:
+    path: /tmp/a.log
+"#,
+    )
+    .unwrap();
+    let overlay: Value = serde_yaml::from_str(
+        r#"
+project:
+  check_health:
+    interval: 5
+args:
+  - key: --b
+    expr: ['3']
+  - key: --c
+    expr: ['4']
+path:
+  - /etc/a
+  - /etc/b
+log:
+  file:
+    path: /tmp/b.log
+"#,
+    )
+    .unwrap();
+    let expected: Value = serde_yaml::from_str(
+        r#"
+project:
+  name: base
+  check_health:
+    delay: 3
+    interval: 5
+args:
+  - key: --a
+    expr: ['1']
+  - key: --b
+    expr: ['3']
+  - key: --c
+    expr: ['4']
+path:
+  - /etc/a
+  - /etc/b
+log:
+  file:
+    path: /tmp/b.log
+"#,
+    )
+    .unwrap();
+    assert_eq!(merge(base, overlay), expected);
+}
+
+#[test]
+fn apply_profiles_test() {
+    let config: Value = serde_yaml::from_str(
+        r#"
+project:
+  name: app
+  restart_policy: ALWAYS
+log:
+  console:
+    level: DEBUG
+profiles:
+  prod:
+    project:
+      restart_policy: FAIL
+    log:
+      console:
+        level: WARN
+  eu:
+    log:
+      console:
+        level: ERROR
+"#,
+    )
+    .unwrap();
+    let value = apply_profiles(config.clone(), &[]).unwrap();
+    assert_eq!(value["log"]["console"]["level"], Value::from("DEBUG"));
+    assert!(value.get("profiles").is_none());
+    let value = apply_profiles(config.clone(), &["prod".to_string(), "eu".to_string()]).unwrap();
+    assert_eq!(value["project"]["restart_policy"], Value::from("FAIL"));
+    assert_eq!(value["log"]["console"]["level"], Value::from("ERROR"));
+    assert!(apply_profiles(config, &["dev".to_string()]).is_err());
+}
+
+#[test]
+fn load_config_tree_test() {
+    let dir = utils::file::new_temp_path("args-compose-test").with_extension("d");
+    fs::create_dir_all(dir.join("common")).unwrap();
+    fs::write(
+        dir.join("common/base.yaml"),
+        "log:\n  file:\n    level: INFO\nattach:\n  env: {{env}}\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("common/signals.yaml"),
+        "project:\n  signals:\n    exit: 2\n",
+    )
+    .unwrap();
+    fs::write(
+        dir.join("app.yaml"),
+        "extends: common/base.yaml\ninclude:\n  - common/signals.yaml\nproject:\n  name: app\n  binary: app.sh\n",
+    )
+    .unwrap();
+    let attrs = vec![("{{env}}".to_string(), "prod".to_string())];
+    let (value, files) = load_config_tree(&dir.join("app.yaml"), &attrs).unwrap();
+    assert_eq!(files.len(), 3);
+    assert_eq!(value["project"]["name"], Value::from("app"));
+    assert_eq!(value["project"]["signals"]["exit"], Value::from(2));
+    assert_eq!(value["log"]["file"]["level"], Value::from("INFO"));
+    assert_eq!(value["attach"]["env"], Value::from("prod"));
+    assert!(value.get("include").is_none());
+
+    fs::write(dir.join("common/base.yaml"), "include: ../app.yaml\n").unwrap();
+    let err = load_config_tree(&dir.join("app.yaml"), &attrs).unwrap_err();
+    assert!(err.to_string().contains("循环引用"));
+    fs::remove_dir_all(dir).ok();
+}
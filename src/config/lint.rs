@@ -0,0 +1,620 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! 配置检查
+//!
+//! 在解析配置的基础上检查无效或危险的配置项，例如未被引用的变量、永远不会被使用的表达式等。
+
+use std::collections::{HashMap, HashSet};
+use std::env;
+use std::fmt::{Display, Formatter};
+use std::ops::Not;
+use std::path::Path;
+
+use libc::{SIGCHLD, SIGHUP, SIGINT, SIGKILL, SIGSTOP, SIGTERM};
+use regex::Regex;
+
+use crate::binary::args_builder::load_path;
+use crate::config::compose::{apply_profiles, load_config_tree, parse_config};
+use crate::config::project_conf::start_order;
+use crate::config::prop::LoggerLevel::{ERROR, NONE, WARN};
+use crate::config::prop::{LoggerLevel, ProjectConfig, SighupAction, WatchdogAction};
+use crate::lib::SoftError;
+use crate::utils::signal::{is_valid_signal, signal_name};
+use crate::utils::string::{find_variables, replace_all_str_from_map};
+
+pub struct LintIssue {
+    pub level: LoggerLevel,
+    pub field: String,
+    pub message: String,
+}
+
+impl Display for LintIssue {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} - {}: {}", self.level, self.field, self.message)
+    }
+}
+
+/**
+检查配置文件，返回发现的全部问题
+
+与启动时不同，检查时不会替换配置中的变量，也不会校验可执行文件
+ */
+pub fn lint(
+    config_path: &str,
+    attrs: &HashMap<String, String>,
+    profiles: &[String],
+) -> Result<Vec<LintIssue>, SoftError> {
+    let (config_tree, _) = load_config_tree(Path::new(config_path), &vec![])?;
+    let config = parse_config(&apply_profiles(config_tree, profiles)?)?;
+    let mut attrs = attrs.clone();
+    attrs.insert("profile".to_string(), profiles.join(","));
+    Ok(lint_config(&config, &attrs))
+}
+
+pub fn lint_config(config: &ProjectConfig, attrs: &HashMap<String, String>) -> Vec<LintIssue> {
+    let mut issues = vec![];
+    let mut issue = |level: LoggerLevel, field: String, message: String| {
+        issues.push(LintIssue {
+            level,
+            field,
+            message,
+        })
+    };
+    // 加载时即被替换的常量
+    let mut constants = config.attach.clone();
+    for (key, value) in attrs {
+        constants.insert(key.to_string(), value.to_string());
+    }
+    if let Some(program) = config.all_programs().first() {
+        constants.insert(
+            "binary.location".to_string(),
+            program.project.binary.to_string(),
+        );
+    }
+    let constant_vars: HashMap<String, String> = constants
+        .iter()
+        .map(|(key, value)| (format!("{{{{{}}}}}", key), value.to_string()))
+        .collect();
+    // 运行时可用的变量
+    let mut sources: HashMap<String, String> = HashMap::new();
+    for path in &config.path {
+        let mut path = path.to_string();
+        replace_all_str_from_map(&mut path, &constant_vars);
+        load_path(&mut sources, &path).ok();
+    }
+    let mut defined: HashSet<String> = constants.keys().cloned().collect();
+    for key in sources.keys() {
+        defined.insert(key.to_string());
+        defined.insert(format!("var.{}", key));
+    }
+    for (key, _) in env::vars() {
+        defined.insert(format!("env.{}", key));
+        defined.insert(key);
+    }
+
+    for (index, alias) in config.config_alias.iter().enumerate() {
+        for (expr_index, expr) in alias.expr.iter().enumerate() {
+            for variable in find_variables(expr) {
+                if resolvable(&variable, &defined).not() {
+                    issue(
+                        WARN,
+                        format!("config_alias[{}].expr[{}]", index, expr_index),
+                        format!(
+                            "引用了未定义的 key '{}'，除非运行时由环境变量提供",
+                            variable.join(" ? ")
+                        ),
+                    );
+                }
+            }
+        }
+        check_reachable(
+            &format!("config_alias[{}]", index),
+            &alias.expr,
+            None,
+            &constants,
+            &mut issue,
+        );
+        defined.insert(alias.key.to_string());
+    }
+
+    // 被引用的变量
+    let mut referenced: HashSet<String> = HashSet::new();
+    let mut texts = vec![serde_yaml::to_string(config).unwrap_or_default()];
+    texts.extend(sources.values().cloned());
+    for text in &texts {
+        for variable in find_variables(text) {
+            referenced.extend(variable);
+        }
+        for variable in find_script_variables(text) {
+            referenced.insert(variable);
+        }
+    }
+    let mut attach_keys: Vec<&String> = config.attach.keys().collect();
+    attach_keys.sort();
+    for key in attach_keys {
+        if referenced.contains(key).not() {
+            issue(
+                WARN,
+                format!("attach.{}", key),
+                "变量未被任何配置引用".to_string(),
+            );
+        }
+    }
+
+    let file_log = &config.log.file;
+    if file_log.level != NONE
+        && file_log.path.trim().is_empty().not()
+        && file_log.path.trim() == file_log.error_path.trim()
+    {
+        issue(
+            WARN,
+            "log.file".to_string(),
+            format!("path 与 error_path 相同 ({})", file_log.path),
+        );
+    }
+    let mut programs = vec![];
+    if let Some(project) = &config.project {
+        programs.push((
+            "project".to_string(),
+            "args".to_string(),
+            project,
+            &config.args,
+        ));
+    }
+    for (index, program) in config.programs.iter().enumerate() {
+        programs.push((
+            format!("programs[{}]", index),
+            format!("programs[{}].args", index),
+            &program.project,
+            &program.args,
+        ));
+    }
+    if programs.is_empty() {
+        issue(
+            ERROR,
+            "project".to_string(),
+            "未定义任何程序，请配置 project 或 programs".to_string(),
+        );
+    }
+    if let Err(e) = start_order(&config.all_programs()) {
+        issue(ERROR, "programs".to_string(), e.to_string());
+    }
+    for (prefix, args_prefix, project, args) in programs {
+        for (index, arg) in args.iter().enumerate() {
+            let regex = match Regex::new(arg.valid_regex.trim()) {
+                Ok(regex) => regex,
+                Err(e) => {
+                    issue(
+                        ERROR,
+                        format!("{}[{}].valid_regex", args_prefix, index),
+                        format!("正则表达式无效: {}", e),
+                    );
+                    continue;
+                }
+            };
+            check_reachable(
+                &format!("{}[{}]", args_prefix, index),
+                &arg.expr,
+                Some(&regex),
+                &constants,
+                &mut issue,
+            );
+        }
+        let mut script_defined = defined.clone();
+        script_defined.extend(args.iter().map(|e| e.key.to_string()));
+        let scripts = vec![
+            ("before_script", &project.before_script),
+            ("after_script", &project.after_script),
+            ("check_health.script", &project.check_health.script),
+            ("check_started.script", &project.check_started.script),
+            (
+                "check_started.started_script",
+                &project.check_started.started_script,
+            ),
+            ("failure_script", &project.failure_script),
+            ("reload_script", &project.reload_script),
+            ("watchdog.script", &project.watchdog.script),
+        ];
+        for (field, script) in scripts {
+            for variable in find_script_variables(script) {
+                if script_defined.contains(&variable).not() {
+                    issue(
+                        WARN,
+                        format!("{}.{}", prefix, field),
+                        format!("脚本引用的变量 '{{{{{}}}}}' 没有任何来源定义", variable),
+                    );
+                }
+            }
+        }
+        let health = &project.check_health;
+        if health.interval != 0 && health.failures == 0 {
+            issue(
+                ERROR,
+                format!("{}.check_health", prefix),
+                "已设置 interval 但 failures 为 0，程序将被反复重启".to_string(),
+            );
+        }
+        if project.start_limit_burst != 0 && project.start_limit_interval == 0 {
+            issue(
+                WARN,
+                format!("{}.start_limit_interval", prefix),
+                "start_limit_interval 为 0，启动次数限制永远不会生效".to_string(),
+            );
+        }
+        if project.clear_env && project.env_allow.is_empty().not() {
+            issue(
+                WARN,
+                format!("{}.env_allow", prefix),
+                "已设置 clear_env，env_allow 不会生效".to_string(),
+            );
+        }
+        let ranges = [
+            ("nice", project.nice, -20..=19),
+            ("oom_score_adj", project.oom_score_adj, -1000..=1000),
+            (
+                "ionice.level",
+                project.ionice.map(|e| e.level as i32),
+                0..=7,
+            ),
+        ];
+        for (name, value, range) in ranges {
+            if let Some(value) = value.filter(|e| range.contains(e).not()) {
+                issue(
+                    ERROR,
+                    format!("{}.{}", prefix, name),
+                    format!(
+                        "{} 超出取值范围 {} 到 {}",
+                        value,
+                        range.start(),
+                        range.end()
+                    ),
+                );
+            }
+        }
+        let cgroup = &project.cgroup;
+        if cgroup.enabled.not()
+            && [&cgroup.memory_max, &cgroup.cpu_max, &cgroup.pids_max]
+                .iter()
+                .any(|e| e.is_empty().not())
+        {
+            issue(
+                WARN,
+                format!("{}.cgroup", prefix),
+                "未设置 enabled，cgroup 资源上限不会生效".to_string(),
+            );
+        }
+        if project.reload_script.trim().is_empty().not()
+            && project.sighup_action != SighupAction::RELOAD
+        {
+            issue(
+                WARN,
+                format!("{}.reload_script", prefix),
+                "sighup_action 不是 RELOAD，重新加载脚本不会被执行".to_string(),
+            );
+        }
+        if project.max_uptime.filter(|e| e.0.is_zero()).is_some() {
+            issue(
+                ERROR,
+                format!("{}.max_uptime", prefix),
+                "max_uptime 为 0，程序将不断重启".to_string(),
+            );
+        }
+        let schedule = &project.restart_schedule;
+        if schedule.jitter.0.is_zero().not()
+            && schedule.cron.is_none()
+            && project.max_uptime.is_none()
+        {
+            issue(
+                WARN,
+                format!("{}.restart_schedule.jitter", prefix),
+                "未设置 cron 与 max_uptime，jitter 不会生效".to_string(),
+            );
+        }
+        for (index, code) in project.fatal_exit_codes.iter().enumerate() {
+            if project.success_exit_codes.contains(code) {
+                issue(
+                    WARN,
+                    format!("{}.fatal_exit_codes[{}]", prefix, index),
+                    format!(
+                        "退出码 {} 同时位于 success_exit_codes 中，将视为不可恢复的错误",
+                        code
+                    ),
+                );
+            }
+        }
+        for (field, codes) in [
+            ("success_exit_codes", &project.success_exit_codes),
+            ("fatal_exit_codes", &project.fatal_exit_codes),
+        ] {
+            for (index, code) in codes.iter().enumerate() {
+                if (0..=255).contains(code).not() {
+                    issue(
+                        WARN,
+                        format!("{}.{}[{}]", prefix, field, index),
+                        format!("{} 不在 0 到 255 之间，进程的退出码不会是该值", code),
+                    );
+                }
+            }
+        }
+        let watchdog = &project.watchdog;
+        if watchdog.is_enabled() && watchdog.interval.0.is_zero() {
+            issue(
+                ERROR,
+                format!("{}.watchdog.interval", prefix),
+                "采样间隔为 0".to_string(),
+            );
+        }
+        if watchdog.action == WatchdogAction::SCRIPT && watchdog.script.trim().is_empty() {
+            issue(
+                WARN,
+                format!("{}.watchdog.script", prefix),
+                "action 为 SCRIPT，但未设置脚本".to_string(),
+            );
+        }
+        let started = &project.check_started;
+        if started.interval != 0 && started.script.trim().is_empty().not() && started.success == 0 {
+            issue(
+                WARN,
+                format!("{}.check_started.success", prefix),
+                "success 为 0，启动检查将立即视为成功".to_string(),
+            );
+        }
+        let signals = &project.signals;
+        let mut signal_fields = vec![
+            ("signals.reload".to_string(), signals.reload),
+            ("signals.exit".to_string(), signals.exit),
+            ("signals.kill".to_string(), signals.kill),
+            ("watchdog.signal".to_string(), project.watchdog.signal.0),
+        ];
+        for (index, step) in project.stop_sequence.iter().enumerate() {
+            signal_fields.push((format!("stop_sequence[{}].signal", index), step.signal.0));
+        }
+        for (name, signal) in signal_fields {
+            if is_valid_signal(signal).not() {
+                issue(
+                    ERROR,
+                    format!("{}.{}", prefix, name),
+                    format!("{} 不是有效的 Linux 信号量", signal),
+                );
+            }
+        }
+    }
+    for (index, signal) in config.init.forward_signals.iter().enumerate() {
+        let field = format!("init.forward_signals[{}]", index);
+        if is_valid_signal(signal.0).not() {
+            issue(
+                ERROR,
+                field,
+                format!("{} 不是有效的 Linux 信号量", signal.0),
+            );
+        } else if [SIGINT, SIGTERM, SIGHUP, SIGCHLD, SIGKILL, SIGSTOP].contains(&signal.0) {
+            issue(
+                WARN,
+                field,
+                format!(
+                    "{} 由 args-tools 自行处理或无法捕获，不会被转发",
+                    signal_name(signal.0)
+                ),
+            );
+        }
+    }
+    issues
+}
+
+/// 变量的任一候选 key 已定义或默认为空时可计算
+fn resolvable(variable: &[String], defined: &HashSet<String>) -> bool {
+    variable.iter().any(|e| e.is_empty() || defined.contains(e))
+}
+
+/// 检查表达式列表中永远不会被使用的候选项
+fn check_reachable(
+    field: &str,
+    exprs: &[String],
+    regex: Option<&Regex>,
+    constants: &HashMap<String, String>,
+    issue: &mut impl FnMut(LoggerLevel, String, String),
+) {
+    let mut always: Option<usize> = None;
+    for (index, expr) in exprs.iter().enumerate() {
+        if let Some(always) = always {
+            issue(
+                WARN,
+                format!("{}.expr[{}]", field, index),
+                format!("expr[{}] 总能得到有效结果，此候选项永远不会被使用", always),
+            );
+            continue;
+        }
+        if let Some(first) = exprs[..index].iter().position(|e| e == expr) {
+            issue(
+                WARN,
+                format!("{}.expr[{}]", field, index),
+                format!("与 expr[{}] 重复，此候选项永远不会被使用", first),
+            );
+            continue;
+        }
+        let variables = find_variables(expr);
+        let defined: HashSet<String> = constants.keys().cloned().collect();
+        if variables.iter().all(|e| resolvable(e, &defined)).not() {
+            continue;
+        }
+        match (constant_value(expr, constants), regex) {
+            (Some(value), Some(regex)) if regex.is_match(&value).not() => issue(
+                WARN,
+                format!("{}.expr[{}]", field, index),
+                "表达式的结果固定且无法通过 valid_regex 校验，此候选项永远不会被使用".to_string(),
+            ),
+            (Some(_), _) => always = Some(index),
+            (None, Some(regex)) if regex.as_str().is_empty() => always = Some(index),
+            (None, None) => always = Some(index),
+            (None, Some(_)) => {}
+        }
+    }
+}
+
+/// 计算仅由常量组成的表达式的值
+fn constant_value(expr: &str, constants: &HashMap<String, String>) -> Option<String> {
+    let regex = Regex::new("\\{\\{\\w.*?}}").unwrap();
+    let mut value = String::new();
+    let mut last = 0;
+    for item in regex.find_iter(expr) {
+        let first = expr[item.start() + 2..item.end() - 2]
+            .split('?')
+            .next()?
+            .trim();
+        value.push_str(&expr[last..item.start()]);
+        if first.is_empty().not() {
+            value.push_str(constants.get(first)?);
+        }
+        last = item.end();
+    }
+    value.push_str(&expr[last..]);
+    Some(value)
+}
+
+/// 查找脚本中引用的变量，脚本中的变量按 key 原样替换
+fn find_script_variables(script: &str) -> Vec<String> {
+    let regex = Regex::new("\\{\\{([^{}]+?)}}").unwrap();
+    regex
+        .captures_iter(script)
+        .map(|e| e[1].to_string())
+        .collect()
+}
+
+#[test]
+fn lint_config_test() {
+    let config: ProjectConfig = serde_yaml::from_str(
+        r#"
+project:
+  name: app
+  binary: app.sh
+  before_script: |
+    echo {{--address}} {{unknown.key}}
+  check_health:
+    script: exit 0
+    interval: 2
+    failures: 0
+  check_started:
+    script: exit 0
+    interval: 1
+    success: 0
+  signals:
+    reload: 1
+    exit: 99
+  start_limit_burst: 3
+  start_limit_interval: 0
+  clear_env: true
+  env_allow: [PATH]
+  nice: -30
+  oom_score_adj: 100
+  cgroup:
+    memory_max: 1G
+  reload_script: kill -0 $MAINPID
+  restart_schedule:
+    jitter: 5m
+  max_uptime: 0
+  success_exit_codes: [0, 300]
+  fatal_exit_codes: [78, 0]
+  watchdog:
+    interval: 0
+    max_fds: 100
+    action: SCRIPT
+  stop_sequence:
+    - signal: TERM
+    - signal: 0
+args:
+  - key: --address
+    expr:
+      - '{{address}}'
+      - '{{host ? }}'
+      - 'localhost'
+      - 'never'
+  - key: --port
+    expr:
+      - 'abc'
+      - '{{port}}'
+    valid_regex: '^\d+$'
+config_alias:
+  - key: address
+    expr:
+      - '{{missing}}'
+attach:
+  port: '8080'
+  unused: '1'
+log:
+  file:
+    level: INFO
+init:
+  forward_signals: [USR1, TERM, 0]
+"#,
+    )
+    .unwrap();
+    let issues: Vec<String> = lint_config(&config, &HashMap::new())
+        .iter()
+        .map(|e| e.field.to_string())
+        .collect();
+    assert!(issues.contains(&"config_alias[0].expr[0]".to_string()));
+    assert!(issues.contains(&"args[0].expr[2]".to_string()));
+    assert!(issues.contains(&"args[0].expr[3]".to_string()));
+    assert!(issues.contains(&"args[1].expr[0]".to_string()));
+    assert!(issues.contains(&"attach.unused".to_string()));
+    assert!(issues.contains(&"attach.port".to_string()).not());
+    assert!(issues.contains(&"project.before_script".to_string()));
+    assert!(issues.contains(&"project.check_health".to_string()));
+    assert!(issues.contains(&"project.check_started.success".to_string()));
+    assert!(issues.contains(&"log.file".to_string()));
+    assert!(issues.contains(&"project.signals.exit".to_string()));
+    assert!(issues.contains(&"project.signals.reload".to_string()).not());
+    assert!(issues.contains(&"project.start_limit_interval".to_string()));
+    assert!(issues.contains(&"project.stop_sequence[1].signal".to_string()));
+    assert!(issues.contains(&"project.env_allow".to_string()));
+    assert!(issues.contains(&"project.nice".to_string()));
+    assert!(issues.contains(&"project.cgroup".to_string()));
+    assert!(issues.contains(&"project.oom_score_adj".to_string()).not());
+    assert!(issues.contains(&"project.reload_script".to_string()));
+    assert!(issues.contains(&"project.max_uptime".to_string()));
+    assert!(issues.contains(&"project.success_exit_codes[1]".to_string()));
+    assert!(issues.contains(&"project.fatal_exit_codes[1]".to_string()));
+    assert!(issues
+        .contains(&"project.fatal_exit_codes[0]".to_string())
+        .not());
+    assert!(issues.contains(&"project.watchdog.interval".to_string()));
+    assert!(issues.contains(&"project.watchdog.script".to_string()));
+    assert!(issues
+        .contains(&"project.restart_schedule.jitter".to_string())
+        .not());
+    assert!(issues
+        .contains(&"init.forward_signals[0]".to_string())
+        .not());
+    assert!(issues.contains(&"init.forward_signals[1]".to_string()));
+    assert!(issues.contains(&"init.forward_signals[2]".to_string()));
+    assert!(issues
+        .contains(&"project.stop_sequence[0].signal".to_string())
+        .not());
+    assert_eq!(
+        issues
+            .iter()
+            .filter(|e| *e == "project.before_script")
+            .count(),
+        1
+    );
+}
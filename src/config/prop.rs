@@ -22,19 +22,30 @@
 
 use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
+use std::ops::Not;
 use std::str::FromStr;
+use std::time::Duration;
 
 use libc::{SIGHUP, SIGKILL, SIGTERM};
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde_yaml::Value;
 
 use args_tools::SoftError;
 
 use crate::config::prop::RestartPolicy::ALWAYS;
 use crate::config::prop::SourceKeyMode::ARG;
+use crate::utils::cron::CronExpr;
+use crate::utils::signal::signal_from_name;
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ProjectConfig {
-    pub project: ProjectInfo,
+    #[serde(default = "empty_str")]
+    pub extends: String,
+    #[serde(default = "default_vec")]
+    pub include: Vec<String>,
+    pub project: Option<ProjectInfo>,
+    #[serde(default = "default_programs")]
+    pub programs: Vec<ProgramInfo>,
     #[serde(default = "default_args_vec")]
     pub args: Vec<ProjectArgs>,
     #[serde(default = "default_vec")]
@@ -45,6 +56,56 @@ pub struct ProjectConfig {
     pub attach: HashMap<String, String>,
     #[serde(default = "default_alias")]
     pub config_alias: Vec<ProjectConfigAlias>,
+    #[serde(default = "def_sensitive_keys")]
+    pub sensitive_keys: Vec<String>,
+    #[serde(default = "default_profiles")]
+    pub profiles: HashMap<String, Value>,
+    #[serde(default = "def_init")]
+    pub init: InitConfig,
+    #[serde(default = "def_hot_reload")]
+    pub hot_reload: HotReload,
+}
+
+fn default_profiles() -> HashMap<String, Value> {
+    HashMap::new()
+}
+
+fn def_hot_reload() -> HotReload {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+fn def_init() -> InitConfig {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+fn def_sensitive_keys() -> Vec<String> {
+    vec![
+        "password".to_string(),
+        "secret".to_string(),
+        "token".to_string(),
+    ]
+}
+
+impl ProjectConfig {
+    /**
+    全部需要运行的程序，`project` 与顶层 `args` 视为第一个程序
+     */
+    pub fn all_programs(&self) -> Vec<ProgramInfo> {
+        let mut programs = vec![];
+        if let Some(project) = &self.project {
+            programs.push(ProgramInfo {
+                project: project.clone(),
+                args: self.args.clone(),
+                depends_on: vec![],
+            });
+        }
+        programs.extend(self.programs.iter().cloned());
+        programs
+    }
+}
+
+fn default_programs() -> Vec<ProgramInfo> {
+    vec![]
 }
 
 fn default_alias() -> Vec<ProjectConfigAlias> {
@@ -67,15 +128,47 @@ fn default_map() -> HashMap<String, String> {
     HashMap::new()
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ProjectConfigAlias {
     pub key: String,
     pub expr: Vec<String>,
     #[serde(default = "def_over")]
     pub over: bool,
+    #[serde(default = "bool_disable")]
+    pub sensitive: bool,
+}
+
+/// init 模式，作为容器入口 (PID 1) 运行时回收全部子进程并转发信号
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct InitConfig {
+    #[serde(default = "bool_disable")]
+    pub enabled: bool,
+    #[serde(default = "def_forward_signals")]
+    pub forward_signals: Vec<Signal>,
+}
+
+fn def_forward_signals() -> Vec<Signal> {
+    [libc::SIGUSR1, libc::SIGUSR2, libc::SIGWINCH, libc::SIGQUIT]
+        .into_iter()
+        .map(Signal)
+        .collect()
+}
+
+/// 配置热重载，配置文件及本地配置来源变化时重新加载配置
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct HotReload {
+    #[serde(default = "bool_disable")]
+    pub enabled: bool,
+    /// 最后一次变化后等待的时间，避免编辑器多次写入时重复加载
+    #[serde(default = "def_hot_reload_debounce")]
+    pub debounce: TimeSpan,
+}
+
+fn def_hot_reload_debounce() -> TimeSpan {
+    TimeSpan(Duration::from_secs(1))
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ProjectLog {
     #[serde(default = "def_console")]
     pub console: ConsoleLog,
@@ -91,17 +184,17 @@ fn def_file() -> FileLog {
     serde_yaml::from_str("").unwrap()
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ConsoleLog {
     #[serde(default = "console_log_level")]
     pub level: LoggerLevel,
 }
 
 fn console_log_level() -> LoggerLevel {
-    LoggerLevel::NONE
+    LoggerLevel::INFO
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct FileLog {
     #[serde(default = "file_log_level")]
     pub level: LoggerLevel,
@@ -117,6 +210,10 @@ fn bool_enable() -> bool {
     true
 }
 
+fn bool_disable() -> bool {
+    false
+}
+
 fn file_log_path() -> String {
     "/tmp/test.log".to_string()
 }
@@ -186,7 +283,7 @@ fn def_over() -> bool {
     true
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ProjectArgs {
     pub key: String,
     pub expr: Vec<String>,
@@ -198,6 +295,8 @@ pub struct ProjectArgs {
     pub valid_regex: String,
     #[serde(default = "error_message")]
     pub valid_message: String,
+    #[serde(default = "bool_disable")]
+    pub sensitive: bool,
 }
 
 fn error_message() -> String {
@@ -240,7 +339,7 @@ pub enum SourceKeyMode {
     ENV,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct ProjectInfo {
     pub name: String,
     pub binary: String,
@@ -254,12 +353,662 @@ pub struct ProjectInfo {
     pub check_started: StartedCheck,
     #[serde(default = "def_signals")]
     pub signals: SoftSignals,
+    #[serde(default = "empty_str")]
+    pub reload_script: String,
+    #[serde(default = "def_sighup_action")]
+    pub sighup_action: SighupAction,
     #[serde(default = "def_restart_policy")]
     pub restart_policy: RestartPolicy,
+    #[serde(default = "def_success_exit_codes")]
+    pub success_exit_codes: Vec<i32>,
+    #[serde(default = "def_exit_codes")]
+    pub fatal_exit_codes: Vec<i32>,
+    #[serde(default = "def_on_signal")]
+    pub on_signal: ExitClass,
+    #[serde(default = "def_restart_backoff")]
+    pub restart_backoff: RestartBackoff,
+    #[serde(default = "def_restart_schedule")]
+    pub restart_schedule: RestartSchedule,
+    #[serde(default)]
+    pub max_uptime: Option<TimeSpan>,
+    #[serde(default = "usize_zero")]
+    pub start_limit_burst: usize,
+    #[serde(default = "usize_60")]
+    pub start_limit_interval: usize,
+    #[serde(default = "empty_str")]
+    pub failure_script: String,
+    #[serde(default = "def_stop_sequence")]
+    pub stop_sequence: Vec<StopStep>,
+    #[serde(default = "def_stop_timeout")]
+    pub stop_timeout: TimeSpan,
+    #[serde(default = "def_output")]
+    pub output: OutputConfig,
+    #[serde(default = "empty_str")]
+    pub workdir: String,
+    #[serde(default)]
+    pub umask: Option<Umask>,
+    #[serde(default = "bool_disable")]
+    pub clear_env: bool,
+    #[serde(default = "def_env_patterns")]
+    pub env_allow: Vec<String>,
+    #[serde(default = "def_env_patterns")]
+    pub env_deny: Vec<String>,
+    #[serde(default = "def_env")]
+    pub env: HashMap<String, String>,
+    #[serde(default = "empty_str")]
+    pub user: String,
+    #[serde(default = "empty_str")]
+    pub group: String,
+    #[serde(default = "def_env_patterns")]
+    pub supplementary_groups: Vec<String>,
+    #[serde(default = "def_limits")]
+    pub limits: ResourceLimits,
+    #[serde(default)]
+    pub nice: Option<i32>,
+    #[serde(default)]
+    pub ionice: Option<IoNice>,
+    #[serde(default)]
+    pub oom_score_adj: Option<i32>,
+    #[serde(default = "def_cgroup")]
+    pub cgroup: CgroupConfig,
+    #[serde(default = "def_watch")]
+    pub watch: WatchConfig,
+    #[serde(default = "def_watchdog")]
+    pub watchdog: WatchdogConfig,
+    #[serde(default = "def_script_policy")]
+    pub script_policy: ScriptPolicy,
     #[serde(default = "bash_str")]
     pub script_worker: String,
 }
 
+impl ProjectInfo {
+    /**
+    停止程序的步骤，未配置 `stop_sequence` 时先发送 `signals.exit` 等待 `stop_timeout`，再发送 `signals.kill`
+     */
+    pub fn stop_steps(&self) -> Vec<StopStep> {
+        if self.stop_sequence.is_empty().not() {
+            return self.stop_sequence.clone();
+        }
+        vec![
+            StopStep {
+                signal: Signal(self.signals.exit),
+                wait: self.stop_timeout,
+            },
+            StopStep {
+                signal: Signal(self.signals.kill),
+                wait: def_stop_wait(),
+            },
+        ]
+    }
+}
+
+/// 子进程输出的处理方式
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct OutputConfig {
+    #[serde(default = "def_output_mode")]
+    pub stdout: OutputMode,
+    #[serde(default = "def_output_mode")]
+    pub stderr: OutputMode,
+    #[serde(default = "usize_8192")]
+    pub max_line: usize,
+}
+
+///输出方式
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum OutputMode {
+    /// 继承文件描述符，原样输出到控制台
+    PASSTHROUGH,
+    /// 按行转发到日志
+    CAPTURE,
+    /// 原样输出到控制台，同时按行写入文件日志
+    BOTH,
+    /// 丢弃
+    DISCARD,
+}
+
+fn def_output_mode() -> OutputMode {
+    OutputMode::CAPTURE
+}
+
+fn def_output() -> OutputConfig {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+fn usize_8192() -> usize {
+    8192
+}
+
+fn def_env_patterns() -> Vec<String> {
+    vec![]
+}
+
+fn def_env() -> HashMap<String, String> {
+    HashMap::new()
+}
+
+/// 脚本 (钩子与检查脚本) 的执行环境，未配置的项与程序相同
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ScriptPolicy {
+    #[serde(default)]
+    pub workdir: Option<String>,
+    #[serde(default)]
+    pub umask: Option<Umask>,
+    #[serde(default)]
+    pub clear_env: Option<bool>,
+    #[serde(default)]
+    pub env_allow: Option<Vec<String>>,
+    #[serde(default)]
+    pub env_deny: Option<Vec<String>>,
+    #[serde(default = "def_env")]
+    pub env: HashMap<String, String>,
+    #[serde(default)]
+    pub user: Option<String>,
+    #[serde(default)]
+    pub group: Option<String>,
+    #[serde(default)]
+    pub supplementary_groups: Option<Vec<String>>,
+}
+
+fn def_script_policy() -> ScriptPolicy {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+/// 文件创建掩码，按八进制解析，如 `022`、`"0027"`
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Umask(pub u32);
+
+impl FromStr for Umask {
+    type Err = SoftError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        u32::from_str_radix(s.trim(), 8)
+            .ok()
+            .filter(|e| *e <= 0o777)
+            .map(Umask)
+            .ok_or_else(|| SoftError::AppError(format!("无效的 umask {}", s)))
+    }
+}
+
+impl Display for Umask {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:04o}", self.0)
+    }
+}
+
+impl Serialize for Umask {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for Umask {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            // 未加引号的 022 会被解析为数字 22，仍按八进制处理
+            Number(u32),
+            Text(String),
+        }
+        let text = match Raw::deserialize(deserializer)? {
+            Raw::Number(number) => number.to_string(),
+            Raw::Text(text) => text,
+        };
+        text.parse()
+            .map_err(|e: SoftError| serde::de::Error::custom(e.to_string()))
+    }
+}
+
+/// 程序的资源限制，未配置的项继承当前进程
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ResourceLimits {
+    #[serde(default)]
+    pub nofile: Option<RLimit>,
+    #[serde(default)]
+    pub nproc: Option<RLimit>,
+    #[serde(default)]
+    pub core: Option<RLimit>,
+    #[serde(default, rename = "as")]
+    pub address_space: Option<RLimit>,
+    #[serde(default)]
+    pub memlock: Option<RLimit>,
+    #[serde(default)]
+    pub cpu: Option<RLimit>,
+}
+
+fn def_limits() -> ResourceLimits {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+/**
+单项资源限制，可配置为数字、`unlimited` 或 `软限制:硬限制` (如 `1024:4096`)，只配置一个值时软硬限制相同
+ */
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct RLimit {
+    pub soft: u64,
+    pub hard: u64,
+}
+
+/// 不限制
+pub const RLIM_UNLIMITED: u64 = u64::MAX;
+
+impl FromStr for RLimit {
+    type Err = SoftError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let value = |text: &str| -> Result<u64, SoftError> {
+            match text.trim() {
+                "unlimited" | "infinity" => Ok(RLIM_UNLIMITED),
+                text => text
+                    .parse()
+                    .map_err(|_| SoftError::AppError(format!("无效的资源限制 {}", s))),
+            }
+        };
+        let limit = match s.split_once(':') {
+            Some((soft, hard)) => RLimit {
+                soft: value(soft)?,
+                hard: value(hard)?,
+            },
+            None => {
+                let value = value(s)?;
+                RLimit {
+                    soft: value,
+                    hard: value,
+                }
+            }
+        };
+        if limit.soft > limit.hard {
+            return Err(SoftError::AppError(format!(
+                "资源限制 {} 的软限制大于硬限制",
+                s
+            )));
+        }
+        Ok(limit)
+    }
+}
+
+impl Display for RLimit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let value = |value: u64| match value {
+            RLIM_UNLIMITED => "unlimited".to_string(),
+            value => value.to_string(),
+        };
+        write!(f, "{}:{}", value(self.soft), value(self.hard))
+    }
+}
+
+impl Serialize for RLimit {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for RLimit {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(value) => Ok(RLimit {
+                soft: value,
+                hard: value,
+            }),
+            Raw::Text(text) => text
+                .parse()
+                .map_err(|e: SoftError| serde::de::Error::custom(e.to_string())),
+        }
+    }
+}
+
+/// 程序的 cgroup v2 配置，资源上限的格式与 cgroup 接口文件相同，为空时不限制
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct CgroupConfig {
+    #[serde(default = "bool_disable")]
+    pub enabled: bool,
+    #[serde(default = "empty_str")]
+    pub parent: String,
+    #[serde(default = "empty_str")]
+    pub memory_max: String,
+    #[serde(default = "empty_str")]
+    pub cpu_max: String,
+    #[serde(default = "empty_str")]
+    pub pids_max: String,
+}
+
+fn def_cgroup() -> CgroupConfig {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+/// 监听文件变化并重启程序，相对路径相对于程序的工作目录
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct WatchConfig {
+    /// 监听的文件或目录，文件名可以使用通配符
+    #[serde(default = "default_vec")]
+    pub paths: Vec<String>,
+    /// 同时监听可执行文件
+    #[serde(default = "bool_disable")]
+    pub binary: bool,
+    /// 忽略的文件名通配符
+    #[serde(default = "default_vec")]
+    pub exclude: Vec<String>,
+    #[serde(default = "def_watch_debounce")]
+    pub debounce: TimeSpan,
+    /// 文件在此时间内不再变化后才重启，为 0 时不等待
+    #[serde(default = "def_wait_stable")]
+    pub wait_stable: TimeSpan,
+}
+
+impl WatchConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.binary || self.paths.is_empty().not()
+    }
+}
+
+fn def_watch() -> WatchConfig {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+fn def_watch_debounce() -> TimeSpan {
+    TimeSpan(Duration::from_secs(1))
+}
+
+fn def_wait_stable() -> TimeSpan {
+    TimeSpan(Duration::ZERO)
+}
+
+/// 资源看门狗，定时采样程序主进程的资源占用，连续 `samples` 次超出阈值时执行 `action`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct WatchdogConfig {
+    #[serde(default = "def_watchdog_interval")]
+    pub interval: TimeSpan,
+    #[serde(default = "def_watchdog_samples")]
+    pub samples: u32,
+    /// 常驻内存上限
+    #[serde(default)]
+    pub max_rss: Option<ByteSize>,
+    /// CPU 使用率上限 (百分比)，多核时可以超过 100
+    #[serde(default)]
+    pub max_cpu: Option<u32>,
+    #[serde(default)]
+    pub max_fds: Option<u32>,
+    #[serde(default)]
+    pub max_threads: Option<u32>,
+    #[serde(default = "def_watchdog_action")]
+    pub action: WatchdogAction,
+    /// `action` 为 `SCRIPT` 时执行的脚本
+    #[serde(default = "empty_str")]
+    pub script: String,
+    /// `action` 为 `SIGNAL` 时发送的信号
+    #[serde(default = "def_watchdog_signal")]
+    pub signal: Signal,
+}
+
+impl WatchdogConfig {
+    pub fn is_enabled(&self) -> bool {
+        self.max_rss.is_some()
+            || self.max_cpu.is_some()
+            || self.max_fds.is_some()
+            || self.max_threads.is_some()
+    }
+}
+
+/// 资源超出阈值时的动作
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum WatchdogAction {
+    /// 仅输出日志
+    LOG,
+    /// 执行 `watchdog.script`
+    SCRIPT,
+    /// 向程序发送 `watchdog.signal`
+    SIGNAL,
+    /// 重启程序
+    RESTART,
+}
+
+fn def_watchdog() -> WatchdogConfig {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+fn def_watchdog_interval() -> TimeSpan {
+    TimeSpan(Duration::from_secs(10))
+}
+
+fn def_watchdog_samples() -> u32 {
+    3
+}
+
+fn def_watchdog_action() -> WatchdogAction {
+    WatchdogAction::RESTART
+}
+
+fn def_watchdog_signal() -> Signal {
+    Signal(libc::SIGUSR1)
+}
+
+/// IO 调度优先级
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct IoNice {
+    pub class: IoClass,
+    #[serde(default = "ionice_level")]
+    pub level: u8,
+}
+
+fn ionice_level() -> u8 {
+    4
+}
+
+/// IO 调度类型
+#[allow(non_camel_case_types)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum IoClass {
+    REALTIME,
+    BEST_EFFORT,
+    IDLE,
+}
+
+/// 停止程序的单个步骤，发送信号后最多等待 `wait`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct StopStep {
+    pub signal: Signal,
+    #[serde(default = "def_stop_wait")]
+    pub wait: TimeSpan,
+}
+
+fn def_stop_sequence() -> Vec<StopStep> {
+    vec![]
+}
+
+fn def_stop_timeout() -> TimeSpan {
+    TimeSpan(Duration::from_secs(90))
+}
+
+fn def_stop_wait() -> TimeSpan {
+    TimeSpan(Duration::from_secs(10))
+}
+
+/// 信号量，可配置为数字或名称 (如 `TERM`、`SIGTERM`)
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct Signal(pub i32);
+
+impl Serialize for Signal {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_i32(self.0)
+    }
+}
+
+impl<'de> Deserialize<'de> for Signal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Number(i32),
+            Name(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Number(signal) => Ok(Signal(signal)),
+            Raw::Name(name) => signal_from_name(&name)
+                .map(Signal)
+                .ok_or_else(|| serde::de::Error::custom(format!("未知的信号量 {}", name))),
+        }
+    }
+}
+
+/// 时间长度，可配置为秒数或带单位的字符串 (如 `500ms`、`20s`、`5m`、`1h`)
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct TimeSpan(pub Duration);
+
+impl FromStr for TimeSpan {
+    type Err = SoftError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let index = s
+            .find(|e: char| e.is_ascii_digit().not())
+            .unwrap_or(s.len());
+        let (value, unit) = s.split_at(index);
+        let value: u64 = value
+            .parse()
+            .map_err(|_| SoftError::AppError(format!("无效的时间 {}", s)))?;
+        let duration = match unit.trim() {
+            "ms" => Duration::from_millis(value),
+            "" | "s" => Duration::from_secs(value),
+            "m" => Duration::from_secs(value * 60),
+            "h" => Duration::from_secs(value * 3600),
+            _ => return Err(SoftError::AppError(format!("无效的时间单位 {}", s))),
+        };
+        Ok(TimeSpan(duration))
+    }
+}
+
+impl Display for TimeSpan {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        if self.0.subsec_millis() == 0 {
+            write!(f, "{}s", self.0.as_secs())
+        } else {
+            write!(f, "{}ms", self.0.as_millis())
+        }
+    }
+}
+
+impl Serialize for TimeSpan {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for TimeSpan {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Seconds(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Seconds(seconds) => Ok(TimeSpan(Duration::from_secs(seconds))),
+            Raw::Text(text) => text
+                .parse()
+                .map_err(|e: SoftError| serde::de::Error::custom(e.to_string())),
+        }
+    }
+}
+
+/// 字节数，可配置为数字或带单位 (K、M、G、T，按 1024 换算) 的字符串 (如 `512M`)
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct ByteSize(pub u64);
+
+const BYTE_UNITS: [(&str, u64); 4] = [
+    ("T", 1 << 40),
+    ("G", 1 << 30),
+    ("M", 1 << 20),
+    ("K", 1 << 10),
+];
+
+impl FromStr for ByteSize {
+    type Err = SoftError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let index = s
+            .find(|e: char| e.is_ascii_digit().not())
+            .unwrap_or(s.len());
+        let (value, unit) = s.split_at(index);
+        let value: u64 = value
+            .parse()
+            .map_err(|_| SoftError::AppError(format!("无效的大小 {}", s)))?;
+        let unit = unit
+            .trim()
+            .trim_end_matches(['B', 'b'])
+            .to_ascii_uppercase();
+        if unit.is_empty() {
+            return Ok(ByteSize(value));
+        }
+        BYTE_UNITS
+            .iter()
+            .find(|(name, _)| *name == unit)
+            .and_then(|(_, size)| value.checked_mul(*size))
+            .map(ByteSize)
+            .ok_or_else(|| SoftError::AppError(format!("无效的大小单位 {}", s)))
+    }
+}
+
+impl Display for ByteSize {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match BYTE_UNITS
+            .iter()
+            .find(|(_, size)| self.0 != 0 && self.0 & (size - 1) == 0)
+        {
+            Some((name, size)) => write!(f, "{}{}", self.0 / size, name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+impl Serialize for ByteSize {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for ByteSize {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Raw {
+            Bytes(u64),
+            Text(String),
+        }
+        match Raw::deserialize(deserializer)? {
+            Raw::Bytes(bytes) => Ok(ByteSize(bytes)),
+            Raw::Text(text) => text
+                .parse()
+                .map_err(|e: SoftError| serde::de::Error::custom(e.to_string())),
+        }
+    }
+}
+
+/// 一同运行的程序，除 `project` 中的配置外，还拥有独立的参数与依赖
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct ProgramInfo {
+    #[serde(flatten)]
+    pub project: ProjectInfo,
+    #[serde(default = "default_args_vec")]
+    pub args: Vec<ProjectArgs>,
+    #[serde(default = "default_vec")]
+    pub depends_on: Vec<String>,
+}
+
+fn def_restart_backoff() -> RestartBackoff {
+    serde_yaml::from_str("").unwrap()
+}
+
+fn usize_60() -> usize {
+    60
+}
+
 fn def_signals() -> SoftSignals {
     serde_yaml::from_str("").unwrap()
 }
@@ -276,6 +1025,22 @@ fn def_restart_policy() -> RestartPolicy {
     ALWAYS
 }
 
+fn def_success_exit_codes() -> Vec<i32> {
+    vec![0]
+}
+
+fn def_exit_codes() -> Vec<i32> {
+    vec![]
+}
+
+fn def_on_signal() -> ExitClass {
+    ExitClass::FAILURE
+}
+
+fn def_sighup_action() -> SighupAction {
+    SighupAction::RESTART
+}
+
 fn bash_str() -> String {
     "bash".to_string()
 }
@@ -284,7 +1049,7 @@ fn empty_str() -> String {
     "".to_string()
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct HealthCheck {
     #[serde(default = "def_script")]
     pub script: String,
@@ -319,7 +1084,77 @@ pub enum RestartPolicy {
     FAIL,
 }
 
-#[derive(Serialize, Deserialize, PartialEq, Debug)]
+/// 程序退出的分类，决定重启策略如何处理
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ExitClass {
+    /// 正常退出，`FAIL` 策略下不重启
+    SUCCESS,
+    /// 异常退出，按重启策略重启
+    FAILURE,
+    /// 无法恢复的错误，任何策略下都不再重启
+    FATAL,
+}
+
+/// 收到 SIGHUP 时的动作
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum SighupAction {
+    /// 停止并重新启动程序
+    RESTART,
+    /// 向程序发送 `signals.reload`，程序不停止
+    RELOAD,
+}
+
+/// 定时重启，`jitter` 同样作用于 `max_uptime`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub struct RestartSchedule {
+    #[serde(default)]
+    pub cron: Option<CronExpr>,
+    #[serde(default = "def_schedule_timezone")]
+    pub timezone: ScheduleTimezone,
+    /// 在此范围内随机延后重启，避免多个实例同时重启
+    #[serde(default = "def_schedule_jitter")]
+    pub jitter: TimeSpan,
+}
+
+/// cron 表达式使用的时区
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub enum ScheduleTimezone {
+    LOCAL,
+    UTC,
+}
+
+fn def_restart_schedule() -> RestartSchedule {
+    serde_yaml::from_str("{}").unwrap()
+}
+
+fn def_schedule_timezone() -> ScheduleTimezone {
+    ScheduleTimezone::LOCAL
+}
+
+fn def_schedule_jitter() -> TimeSpan {
+    TimeSpan(Duration::ZERO)
+}
+
+/// 重启退避，连续重启时延时逐次翻倍，直到 `max_delay`
+#[derive(Serialize, Deserialize, PartialEq, Debug, Copy, Clone)]
+pub struct RestartBackoff {
+    #[serde(default = "usize_one")]
+    pub delay: usize,
+    #[serde(default = "usize_60")]
+    pub max_delay: usize,
+    #[serde(default = "usize_ten")]
+    pub jitter: usize,
+}
+
+fn usize_one() -> usize {
+    1
+}
+
+fn usize_ten() -> usize {
+    10
+}
+
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub struct StartedCheck {
     #[serde(default = "def_script")]
     pub script: String,
@@ -330,3 +1165,68 @@ pub struct StartedCheck {
     #[serde(default = "def_script")]
     pub started_script: String,
 }
+
+#[test]
+fn stop_step_test() {
+    let steps: Vec<StopStep> = serde_yaml::from_str(
+        "[{signal: TERM, wait: 20s}, {signal: 2, wait: 500ms}, {signal: SIGKILL}]",
+    )
+    .unwrap();
+    assert_eq!(steps[0].signal, Signal(SIGTERM));
+    assert_eq!(steps[0].wait, TimeSpan(Duration::from_secs(20)));
+    assert_eq!(steps[1].signal, Signal(libc::SIGINT));
+    assert_eq!(steps[1].wait, TimeSpan(Duration::from_millis(500)));
+    assert_eq!(steps[2].signal, Signal(SIGKILL));
+    assert_eq!(steps[2].wait, def_stop_wait());
+    assert_eq!(
+        TimeSpan::from_str("5m").unwrap().0,
+        Duration::from_secs(300)
+    );
+    assert_eq!(TimeSpan(Duration::from_millis(1500)).to_string(), "1500ms");
+    assert!(TimeSpan::from_str("5 days").is_err());
+    assert!(serde_yaml::from_str::<StopStep>("{signal: NOPE}").is_err());
+}
+
+#[test]
+fn byte_size_test() {
+    assert_eq!(ByteSize::from_str("512M").unwrap().0, 512 << 20);
+    assert_eq!(ByteSize::from_str("1gb").unwrap().0, 1 << 30);
+    assert_eq!(ByteSize::from_str("100").unwrap().0, 100);
+    assert_eq!(ByteSize(3 << 30).to_string(), "3G");
+    assert_eq!(ByteSize(1536).to_string(), "1536");
+    assert_eq!(ByteSize(1500).to_string(), "1500");
+    assert!(ByteSize::from_str("5X").is_err());
+    assert!(ByteSize::from_str("M").is_err());
+    assert_eq!(serde_yaml::from_str::<ByteSize>("2048").unwrap().0, 2048);
+}
+
+#[test]
+fn rlimit_test() {
+    let limits: ResourceLimits =
+        serde_yaml::from_str("{nofile: 65536, core: unlimited, as: '1024:unlimited'}").unwrap();
+    assert_eq!(
+        limits.nofile,
+        Some(RLimit {
+            soft: 65536,
+            hard: 65536
+        })
+    );
+    assert_eq!(limits.core.unwrap().hard, RLIM_UNLIMITED);
+    assert_eq!(limits.address_space.unwrap().soft, 1024);
+    assert_eq!(limits.nproc, None);
+    assert_eq!(limits.address_space.unwrap().to_string(), "1024:unlimited");
+    assert!(RLimit::from_str("10:5").is_err());
+    assert!(RLimit::from_str("many").is_err());
+}
+
+#[test]
+fn umask_test() {
+    assert_eq!(serde_yaml::from_str::<Umask>("022").unwrap(), Umask(0o22));
+    assert_eq!(
+        serde_yaml::from_str::<Umask>("'0027'").unwrap(),
+        Umask(0o27)
+    );
+    assert_eq!(Umask(0o22).to_string(), "0022");
+    assert!(serde_yaml::from_str::<Umask>("'089'").is_err());
+    assert!(serde_yaml::from_str::<Umask>("'1000'").is_err());
+}
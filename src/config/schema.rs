@@ -0,0 +1,1013 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! 配置文件的 JSON Schema
+//!
+//! 字段默认值由 [`crate::config::prop`] 中的 serde 默认值生成，字段与枚举值由测试保证与结构体同步。
+
+use std::ops::Not;
+
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+
+use crate::config::prop::{
+    CgroupConfig, ConsoleLog, ExitClass, FileLog, HealthCheck, HotReload, InitConfig, IoClass,
+    LoggerLevel, OutputConfig, OutputMode, ProgramInfo, ProjectArgs, ProjectConfig,
+    ProjectConfigAlias, ProjectInfo, ProjectLog, ResourceLimits, RestartBackoff, RestartPolicy,
+    RestartSchedule, ScheduleTimezone, ScriptPolicy, SighupAction, SoftSignals, SourceKeyMode,
+    StartedCheck, StopStep, WatchConfig, WatchdogAction, WatchdogConfig,
+};
+
+const LOGGER_LEVELS: [LoggerLevel; 6] = [
+    LoggerLevel::TRACE,
+    LoggerLevel::DEBUG,
+    LoggerLevel::INFO,
+    LoggerLevel::WARN,
+    LoggerLevel::ERROR,
+    LoggerLevel::NONE,
+];
+
+const SOURCE_KEY_MODES: [SourceKeyMode; 2] = [SourceKeyMode::ARG, SourceKeyMode::ENV];
+
+const OUTPUT_MODES: [OutputMode; 4] = [
+    OutputMode::PASSTHROUGH,
+    OutputMode::CAPTURE,
+    OutputMode::BOTH,
+    OutputMode::DISCARD,
+];
+
+const IO_CLASSES: [IoClass; 3] = [IoClass::REALTIME, IoClass::BEST_EFFORT, IoClass::IDLE];
+
+const SIGHUP_ACTIONS: [SighupAction; 2] = [SighupAction::RESTART, SighupAction::RELOAD];
+
+const SCHEDULE_TIMEZONES: [ScheduleTimezone; 2] = [ScheduleTimezone::LOCAL, ScheduleTimezone::UTC];
+
+const WATCHDOG_ACTIONS: [WatchdogAction; 4] = [
+    WatchdogAction::LOG,
+    WatchdogAction::SCRIPT,
+    WatchdogAction::SIGNAL,
+    WatchdogAction::RESTART,
+];
+
+const EXIT_CLASSES: [ExitClass; 3] = [ExitClass::SUCCESS, ExitClass::FAILURE, ExitClass::FATAL];
+
+const RESTART_POLICIES: [RestartPolicy; 3] = [
+    RestartPolicy::NONE,
+    RestartPolicy::ALWAYS,
+    RestartPolicy::FAIL,
+];
+
+/**
+生成配置文件的 JSON Schema
+ */
+pub fn config_schema() -> Value {
+    let mut schema = project_config();
+    schema["$schema"] = json!("http://json-schema.org/draft-07/schema#");
+    schema["title"] = json!("ArgsCovert 配置文件");
+    schema
+}
+
+fn project_config() -> Value {
+    object(
+        "配置文件",
+        defaults::<ProjectConfig>("{}"),
+        &[],
+        vec![
+            (
+                "extends",
+                string("继承的基础配置文件，路径相对于当前配置文件"),
+            ),
+            (
+                "include",
+                string_list("引入的配置文件列表，路径相对于当前配置文件"),
+            ),
+            ("project", nullable(project_info())),
+            ("programs", array("一同运行的多个程序", program_info())),
+            ("args", array("传入参数", project_args())),
+            ("path", string_list("配置文件路径")),
+            ("log", project_log()),
+            ("attach", map("内部替换变量", scalar())),
+            ("config_alias", array("配置别名", config_alias())),
+            (
+                "sensitive_keys",
+                string_list("敏感 key 规则，key 中包含以下内容(忽略大小写)的值均视为敏感内容"),
+            ),
+            (
+                "profiles",
+                map(
+                    "配置档案，通过 --profile 或环境变量 ARGS_PROFILE 启用，合并规则与 include 相同",
+                    json!({"type": "object"}),
+                ),
+            ),
+            ("init", init_config()),
+            ("hot_reload", hot_reload()),
+        ],
+    )
+}
+
+fn hot_reload() -> Value {
+    object(
+        "配置热重载，配置文件、include 引用的文件与本地配置来源变化时重新加载配置",
+        defaults::<HotReload>("{}"),
+        &[],
+        vec![
+            ("enabled", boolean("是否启用")),
+            (
+                "debounce",
+                time_span("最后一次变化后等待的时间，避免编辑器多次写入时重复加载"),
+            ),
+        ],
+    )
+}
+
+fn init_config() -> Value {
+    object(
+        "init 模式，作为容器入口 (PID 1) 运行时自动启用，也可通过 --init 启用",
+        defaults::<InitConfig>("{}"),
+        &[],
+        vec![
+            ("enabled", boolean("是否启用")),
+            (
+                "forward_signals",
+                array(
+                    "转发给程序进程组的信号量",
+                    signal("数字或名称，如 USR1、SIGUSR1"),
+                ),
+            ),
+        ],
+    )
+}
+
+fn project_info() -> Value {
+    object(
+        "项目信息",
+        defaults::<ProjectInfo>("{name: '', binary: ''}"),
+        &["name", "binary"],
+        project_info_properties(),
+    )
+}
+
+fn program_info() -> Value {
+    let mut properties = project_info_properties();
+    properties.push(("args", array("程序的传入参数", project_args())));
+    properties.push((
+        "depends_on",
+        string_list("依赖的程序名称，依赖的程序启动完成后才会启动当前程序"),
+    ));
+    object(
+        "程序信息",
+        defaults::<ProgramInfo>("{name: '', binary: ''}"),
+        &["name", "binary"],
+        properties,
+    )
+}
+
+fn project_info_properties() -> Vec<(&'static str, Value)> {
+    vec![
+        ("name", string("项目名称")),
+        ("binary", string("可执行文件位置")),
+        (
+            "before_script",
+            string("启动前脚本，可用于前置检查，如果脚本异常退出则视为此次启动失败"),
+        ),
+        ("after_script", string("程序退出后脚本，可用于回收数据")),
+        ("check_health", health_check()),
+        ("check_started", started_check()),
+        ("signals", soft_signals()),
+        (
+            "reload_script",
+            string("重新加载脚本，在发送 signals.reload 之前执行，返回码不为 0 时不发送信号，环境变量 MAINPID 为程序的进程号"),
+        ),
+        (
+            "sighup_action",
+            enumeration(
+                "收到 SIGHUP 时的动作: RESTART 重启程序，RELOAD 执行 reload_script 并发送 signals.reload",
+                &SIGHUP_ACTIONS,
+            ),
+        ),
+        (
+            "restart_policy",
+            enumeration(
+                "重启策略: NONE 不重启，ALWAYS 总是重启，FAIL 失败重启",
+                &RESTART_POLICIES,
+            ),
+        ),
+        (
+            "success_exit_codes",
+            array(
+                "视为正常退出的退出码，FAIL 策略下不重启",
+                json!({"type": "integer"}),
+            ),
+        ),
+        (
+            "fatal_exit_codes",
+            array(
+                "视为不可恢复错误的退出码，任何策略下都不再重启，优先于 success_exit_codes",
+                json!({"type": "integer"}),
+            ),
+        ),
+        (
+            "on_signal",
+            enumeration(
+                "程序被信号结束时的分类: SUCCESS 正常退出，FAILURE 异常退出，FATAL 不再重启",
+                &EXIT_CLASSES,
+            ),
+        ),
+        ("restart_backoff", restart_backoff()),
+        ("restart_schedule", restart_schedule()),
+        (
+            "max_uptime",
+            nullable(time_span("最长运行时间，超过后重启程序，为空时不限制")),
+        ),
+        (
+            "start_limit_burst",
+            integer(
+                "start_limit_interval 内允许的最大启动次数，超出后程序进入失败状态，为 0 时不限制",
+            ),
+        ),
+        (
+            "start_limit_interval",
+            integer("启动次数限制的统计时间(秒)"),
+        ),
+        (
+            "failure_script",
+            string("程序因启动次数超出限制而失败时执行的脚本"),
+        ),
+        (
+            "stop_sequence",
+            array(
+                "停止步骤，依次发送信号并等待程序退出，为空时先发送 signals.exit 等待 stop_timeout，再发送 signals.kill",
+                stop_step(),
+            ),
+        ),
+        (
+            "stop_timeout",
+            time_span("未配置 stop_sequence 时，发送退出信号后等待的时间"),
+        ),
+        ("output", output_config()),
+        (
+            "workdir",
+            string("工作目录，相对路径基于可执行文件所在目录，为空时使用可执行文件所在目录"),
+        ),
+        (
+            "umask",
+            nullable(umask("文件创建掩码，按八进制解析，为空时继承当前进程")),
+        ),
+        ("clear_env", boolean("是否不继承当前进程的环境变量")),
+        (
+            "env_allow",
+            string_list("允许继承的环境变量，支持 * 与 ? 通配符，为空时允许全部"),
+        ),
+        (
+            "env_deny",
+            string_list("禁止继承的环境变量，支持 * 与 ? 通配符"),
+        ),
+        ("env", map("静态环境变量，支持模板变量", json!({"type": "string"}))),
+        (
+            "user",
+            string("运行用户，名称或数字，为空时不切换用户"),
+        ),
+        (
+            "group",
+            string("运行用户组，名称或数字，为空时使用用户的主用户组"),
+        ),
+        (
+            "supplementary_groups",
+            string_list("附加用户组，为空时使用用户所属的全部用户组"),
+        ),
+        ("limits", resource_limits()),
+        ("nice", nullable(integer("进程优先级，取值范围 -20 到 19"))),
+        ("ionice", nullable(io_nice())),
+        (
+            "oom_score_adj",
+            nullable(integer("OOM 评分调整，取值范围 -1000 到 1000")),
+        ),
+        ("cgroup", cgroup_config()),
+        ("watch", watch_config()),
+        ("watchdog", watchdog_config()),
+        ("script_policy", script_policy()),
+        ("script_worker", string("脚本解释器")),
+    ]
+}
+
+fn resource_limits() -> Value {
+    object(
+        "资源限制，未配置的项继承当前进程",
+        defaults::<ResourceLimits>("{}"),
+        &[],
+        vec![
+            ("nofile", nullable(rlimit("最大打开文件数"))),
+            ("nproc", nullable(rlimit("最大进程数"))),
+            ("core", nullable(rlimit("core 文件大小(字节)"))),
+            ("as", nullable(rlimit("虚拟内存大小(字节)"))),
+            ("memlock", nullable(rlimit("锁定内存大小(字节)"))),
+            ("cpu", nullable(rlimit("CPU 时间(秒)"))),
+        ],
+    )
+}
+
+fn cgroup_config() -> Value {
+    object(
+        "cgroup v2 配置，为程序创建独立的 cgroup",
+        defaults::<CgroupConfig>("{}"),
+        &[],
+        vec![
+            ("enabled", boolean("是否启用")),
+            (
+                "parent",
+                string("父 cgroup，相对于 cgroup v2 挂载点，为空时使用当前进程所在的 cgroup"),
+            ),
+            ("memory_max", string("memory.max，如 512M，为空时不限制")),
+            (
+                "cpu_max",
+                string("cpu.max，如 '50000 100000' 表示半个 CPU，为空时不限制"),
+            ),
+            ("pids_max", string("pids.max，为空时不限制")),
+        ],
+    )
+}
+
+fn watch_config() -> Value {
+    object(
+        "监听文件变化并重启程序，相对路径相对于程序的工作目录",
+        defaults::<WatchConfig>("{}"),
+        &[],
+        vec![
+            (
+                "paths",
+                string_list("监听的文件或目录，目录只监听其中的文件，文件名可以使用通配符，如 lib/*.jar"),
+            ),
+            ("binary", boolean("是否同时监听可执行文件")),
+            ("exclude", string_list("忽略的文件名通配符，如 *.tmp")),
+            ("debounce", time_span("最后一次变化后等待的时间")),
+            (
+                "wait_stable",
+                time_span("文件大小与修改时间在此时间内不再变化后才重启，避免启动未复制完成的文件，为 0 时不等待"),
+            ),
+        ],
+    )
+}
+
+fn watchdog_config() -> Value {
+    object(
+        "资源看门狗，定时采样程序主进程的资源占用，任一项连续 samples 次超出阈值时执行 action，未配置阈值时不启用",
+        defaults::<WatchdogConfig>("{}"),
+        &[],
+        vec![
+            ("interval", time_span("采样间隔")),
+            ("samples", integer("连续超出阈值的采样次数")),
+            ("max_rss", nullable(byte_size("常驻内存上限，如 512M"))),
+            (
+                "max_cpu",
+                nullable(integer("CPU 使用率上限(百分比)，多核时可以超过 100")),
+            ),
+            ("max_fds", nullable(integer("打开文件描述符数量上限"))),
+            ("max_threads", nullable(integer("线程数上限"))),
+            (
+                "action",
+                enumeration(
+                    "超出阈值时的动作: LOG 输出日志，SCRIPT 执行 script，SIGNAL 发送 signal，RESTART 重启程序",
+                    &WATCHDOG_ACTIONS,
+                ),
+            ),
+            (
+                "script",
+                string("action 为 SCRIPT 时执行的脚本，环境变量 MAINPID 为程序的进程号，WATCHDOG_REASON 为超出的项"),
+            ),
+            ("signal", signal("action 为 SIGNAL 时发送的信号")),
+        ],
+    )
+}
+
+fn io_nice() -> Value {
+    json!({
+        "type": "object",
+        "description": "IO 调度优先级",
+        "required": ["class"],
+        "additionalProperties": false,
+        "properties": {
+            "class": enumeration("调度类型", &IO_CLASSES),
+            "level": integer("优先级，取值范围 0 到 7，IDLE 时无效"),
+        },
+    })
+}
+
+fn script_policy() -> Value {
+    object(
+        "钩子与检查脚本的执行环境，未配置的项与程序相同",
+        defaults::<ScriptPolicy>("{}"),
+        &[],
+        vec![
+            (
+                "workdir",
+                nullable(string(
+                    "工作目录，未配置时与程序相同，均未配置时使用当前目录",
+                )),
+            ),
+            ("umask", nullable(umask("文件创建掩码"))),
+            (
+                "clear_env",
+                nullable(boolean("是否不继承当前进程的环境变量")),
+            ),
+            ("env_allow", nullable(string_list("允许继承的环境变量"))),
+            ("env_deny", nullable(string_list("禁止继承的环境变量"))),
+            (
+                "env",
+                map(
+                    "静态环境变量，与程序的静态环境变量合并",
+                    json!({"type": "string"}),
+                ),
+            ),
+            ("user", nullable(string("运行用户，未配置时与程序相同"))),
+            ("group", nullable(string("运行用户组，未配置时与程序相同"))),
+            (
+                "supplementary_groups",
+                nullable(string_list("附加用户组，未配置时与程序相同")),
+            ),
+        ],
+    )
+}
+
+fn output_config() -> Value {
+    object(
+        "子进程输出",
+        defaults::<OutputConfig>("{}"),
+        &[],
+        vec![
+            ("stdout", enumeration("标准输出的输出方式", &OUTPUT_MODES)),
+            ("stderr", enumeration("错误输出的输出方式", &OUTPUT_MODES)),
+            (
+                "max_line",
+                integer("单行最大字节数，超出的部分会拆分为多行"),
+            ),
+        ],
+    )
+}
+
+fn stop_step() -> Value {
+    object(
+        "停止步骤",
+        defaults::<StopStep>("{signal: TERM}"),
+        &["signal"],
+        vec![
+            (
+                "signal",
+                signal("发送的信号量，可使用数字或名称，如 TERM、SIGINT"),
+            ),
+            ("wait", time_span("发送信号后最多等待的时间")),
+        ],
+    )
+}
+
+fn restart_backoff() -> Value {
+    object(
+        "重启退避，连续重启时延时逐次翻倍",
+        defaults::<RestartBackoff>("{}"),
+        &[],
+        vec![
+            ("delay", integer("首次重启延时(秒)，为 0 时立即重启")),
+            (
+                "max_delay",
+                integer("最大重启延时(秒)，运行超过此时间后重新计算延时"),
+            ),
+            ("jitter", integer("延时随机浮动的百分比")),
+        ],
+    )
+}
+
+fn restart_schedule() -> Value {
+    object(
+        "定时重启，与 SIGHUP 重启相同，按停止步骤停止程序并执行钩子",
+        defaults::<RestartSchedule>("{}"),
+        &[],
+        vec![
+            (
+                "cron",
+                json!({
+                    "type": ["string", "null"],
+                    "description": "cron 表达式 (分 时 日 月 周)，如 '0 3 * * *' 表示每天 3 点，也可以使用 @daily 等简写",
+                }),
+            ),
+            (
+                "timezone",
+                enumeration(
+                    "cron 表达式的时区: LOCAL 本地时间，UTC 协调世界时",
+                    &SCHEDULE_TIMEZONES,
+                ),
+            ),
+            (
+                "jitter",
+                time_span("在此范围内随机延后重启，同样作用于 max_uptime"),
+            ),
+        ],
+    )
+}
+
+fn health_check() -> Value {
+    object(
+        "程序健康检查",
+        defaults::<HealthCheck>("{}"),
+        &[],
+        vec![
+            (
+                "script",
+                string("程序健康检查脚本，如果返回值不为 0 则视为出现问题"),
+            ),
+            ("delay", integer("开始检测延时(秒)")),
+            ("interval", integer("检查间隔(秒)，为 0 时不检查")),
+            ("failures", integer("视为失败的错误次数")),
+        ],
+    )
+}
+
+fn started_check() -> Value {
+    object(
+        "程序启动完成检查",
+        defaults::<StartedCheck>("{}"),
+        &[],
+        vec![
+            (
+                "script",
+                string("程序启动检查脚本，如果返回值不为 0 则视为未启动完成"),
+            ),
+            ("interval", integer("检查间隔(秒)，为 0 时不检查")),
+            ("success", integer("视为成功的次数")),
+            ("started_script", string("程序启动完成钩子回调")),
+        ],
+    )
+}
+
+fn soft_signals() -> Value {
+    object(
+        "重启信号量",
+        defaults::<SoftSignals>("{}"),
+        &[],
+        vec![
+            ("reload", integer("重新加载信号量")),
+            ("exit", integer("退出信号量")),
+            ("kill", integer("强制结束信号量")),
+        ],
+    )
+}
+
+fn project_args() -> Value {
+    object(
+        "传入参数",
+        defaults::<ProjectArgs>("{key: '', expr: []}"),
+        &["key", "expr"],
+        vec![
+            ("key", string("传入参数 key")),
+            (
+                "expr",
+                string_list("可以用模板变量填充，如果没找到对应的变量，则视为匹配失败"),
+            ),
+            (
+                "mode",
+                enumeration("参数类型: ARG 启动参数，ENV 环境变量", &SOURCE_KEY_MODES),
+            ),
+            ("must", boolean("是否为必选")),
+            ("valid_regex", string("参数正则校验")),
+            (
+                "valid_message",
+                string("校验失败提示，可使用 {{message.key}} 与 {{message.value}}"),
+            ),
+            (
+                "sensitive",
+                boolean("是否为敏感内容，敏感内容在日志与预览中会被遮盖"),
+            ),
+        ],
+    )
+}
+
+fn config_alias() -> Value {
+    object(
+        "配置别名",
+        defaults::<ProjectConfigAlias>("{key: '', expr: []}"),
+        &["key", "expr"],
+        vec![
+            ("key", string("别名 key")),
+            (
+                "expr",
+                string_list("别名表达式，按顺序取第一个可计算的结果"),
+            ),
+            ("over", boolean("是否覆盖已存在的同名配置")),
+            (
+                "sensitive",
+                boolean("是否为敏感内容，敏感内容在日志与预览中会被遮盖"),
+            ),
+        ],
+    )
+}
+
+fn project_log() -> Value {
+    object(
+        "日志信息",
+        defaults::<ProjectLog>("{}"),
+        &[],
+        vec![
+            (
+                "console",
+                object(
+                    "控制台日志",
+                    defaults::<ConsoleLog>("{}"),
+                    &[],
+                    vec![("level", enumeration("日志级别", &LOGGER_LEVELS))],
+                ),
+            ),
+            (
+                "file",
+                object(
+                    "文件日志",
+                    defaults::<FileLog>("{}"),
+                    &[],
+                    vec![
+                        ("level", enumeration("日志级别", &LOGGER_LEVELS)),
+                        ("path", string("标准日志位置")),
+                        ("error_path", string("错误日志位置")),
+                        ("append", boolean("日志追加模式")),
+                    ],
+                ),
+            ),
+        ],
+    )
+}
+
+/// 由 serde 默认值生成的字段默认值
+fn defaults<T: DeserializeOwned + Serialize>(yaml: &str) -> Value {
+    let data: T = serde_yaml::from_str(yaml).unwrap();
+    serde_json::to_value(data).unwrap()
+}
+
+fn object(
+    description: &str,
+    defaults: Value,
+    required: &[&str],
+    properties: Vec<(&str, Value)>,
+) -> Value {
+    let mut items = Map::new();
+    for (name, mut schema) in properties {
+        if required.contains(&name).not() && schema["type"] != json!("object") {
+            if let Some(default) = defaults.get(name) {
+                schema["default"] = default.clone();
+            }
+        }
+        items.insert(name.to_string(), schema);
+    }
+    json!({
+        "type": "object",
+        "description": description,
+        "properties": items,
+        "required": required,
+        "additionalProperties": false,
+    })
+}
+
+/// 对应 `Option` 类型的字段，允许为 null
+fn nullable(mut schema: Value) -> Value {
+    let mut types = match schema["type"].take() {
+        Value::Array(types) => types,
+        item => vec![item],
+    };
+    types.push(json!("null"));
+    schema["type"] = Value::Array(types);
+    schema
+}
+
+fn string(description: &str) -> Value {
+    json!({"type": "string", "description": description})
+}
+
+/// 可转换为字符串的标量
+fn scalar() -> Value {
+    json!({"type": ["string", "number", "boolean"]})
+}
+
+/// 数字或名称形式的信号量
+fn signal(description: &str) -> Value {
+    json!({"type": ["integer", "string"], "description": description})
+}
+
+/// 数字、`unlimited` 或 `软限制:硬限制` 形式的资源限制
+fn rlimit(description: &str) -> Value {
+    json!({
+        "type": ["integer", "string"],
+        "pattern": "^(\\d+|unlimited|infinity)(:(\\d+|unlimited|infinity))?$",
+        "description": description,
+    })
+}
+
+/// 八进制的文件创建掩码
+fn umask(description: &str) -> Value {
+    json!({
+        "type": ["integer", "string"],
+        "pattern": "^[0-7]{1,4}$",
+        "description": description,
+    })
+}
+
+/// 字节数或带单位 (K、M、G、T) 的大小
+fn byte_size(description: &str) -> Value {
+    json!({
+        "type": ["integer", "string"],
+        "pattern": "^\\d+([KkMmGgTt][Bb]?)?$",
+        "description": description,
+    })
+}
+
+/// 秒数或带单位 (ms、s、m、h) 的时间
+fn time_span(description: &str) -> Value {
+    json!({
+        "type": ["integer", "string"],
+        "pattern": "^\\d+(ms|s|m|h)?$",
+        "description": description,
+    })
+}
+
+fn integer(description: &str) -> Value {
+    json!({"type": "integer", "description": description})
+}
+
+fn boolean(description: &str) -> Value {
+    json!({"type": "boolean", "description": description})
+}
+
+fn string_list(description: &str) -> Value {
+    array(description, json!({"type": "string"}))
+}
+
+fn array(description: &str, items: Value) -> Value {
+    json!({"type": "array", "description": description, "items": items})
+}
+
+fn map(description: &str, values: Value) -> Value {
+    json!({"type": "object", "description": description, "additionalProperties": values})
+}
+
+fn enumeration<T: Serialize>(description: &str, variants: &[T]) -> Value {
+    json!({
+        "type": "string",
+        "description": description,
+        "enum": serde_json::to_value(variants).unwrap(),
+    })
+}
+
+/// 校验数据中出现的每个字段都在 schema 中声明，且 schema 中声明的字段都存在于数据中
+#[cfg(test)]
+fn assert_in_sync(path: &str, schema: &Value, data: &Value) {
+    match data {
+        Value::Object(data) if schema.get("properties").is_some() => {
+            let properties = schema["properties"].as_object().unwrap();
+            for (key, value) in data {
+                let field = properties
+                    .get(key)
+                    .unwrap_or_else(|| panic!("schema 缺少字段 {}.{}", path, key));
+                assert_in_sync(&format!("{}.{}", path, key), field, value);
+            }
+            for key in properties.keys() {
+                assert!(
+                    data.contains_key(key),
+                    "schema 存在多余字段 {}.{}",
+                    path,
+                    key
+                );
+            }
+        }
+        Value::Object(data) => {
+            let values = &schema["additionalProperties"];
+            if values.is_object().not() {
+                // 不限制内容的映射，例如配置档案
+                assert_eq!(schema["type"], json!("object"), "{}", path);
+                return;
+            }
+            for (key, value) in data {
+                assert_in_sync(&format!("{}.{}", path, key), values, value);
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                assert_in_sync(&format!("{}[]", path), &schema["items"], item);
+            }
+        }
+        Value::String(value) => {
+            if let Some(variants) = schema.get("enum") {
+                assert!(
+                    variants.as_array().unwrap().contains(&json!(value)),
+                    "schema 字段 {} 缺少枚举值 {}",
+                    path,
+                    value
+                );
+            } else {
+                assert!(has_type(schema, "string"), "{}", path);
+            }
+        }
+        Value::Number(_) => assert!(has_type(schema, "integer"), "{}", path),
+        Value::Bool(_) => assert!(has_type(schema, "boolean"), "{}", path),
+        Value::Null => assert!(
+            has_type(schema, "null"),
+            "schema 字段 {} 不允许为 null",
+            path
+        ),
+    }
+}
+
+#[cfg(test)]
+fn has_type(schema: &Value, name: &str) -> bool {
+    match &schema["type"] {
+        Value::Array(types) => types.contains(&json!(name)),
+        item => item == &json!(name),
+    }
+}
+
+#[test]
+fn config_schema_sync_test() {
+    let config: ProjectConfig = serde_yaml::from_str(
+        r#"
+project:
+  name: app
+  binary: app.sh
+  reload_script: nginx -t
+  sighup_action: RELOAD
+  success_exit_codes: [0, 143]
+  fatal_exit_codes: [78]
+  on_signal: FATAL
+  restart_schedule:
+    cron: '0 3 * * *'
+    timezone: UTC
+    jitter: 10m
+  max_uptime: 24h
+  stop_sequence:
+    - signal: TERM
+      wait: 20s
+    - signal: 9
+  umask: 022
+  env_deny: ['*_TOKEN']
+  env:
+    MODE: prod
+  user: app
+  supplementary_groups: [audio]
+  limits:
+    nofile: 65536
+    core: unlimited
+  nice: 5
+  ionice: {class: BEST_EFFORT, level: 7}
+  oom_score_adj: 500
+  cgroup:
+    enabled: true
+    memory_max: 512M
+  watch:
+    paths: [lib/*.jar]
+    binary: true
+    exclude: ['*.tmp']
+    wait_stable: 2s
+  watchdog:
+    max_rss: 512M
+    max_cpu: 150
+    max_fds: 1000
+    max_threads: 200
+    action: SIGNAL
+    signal: QUIT
+  script_policy:
+    workdir: /tmp
+    user: root
+    clear_env: true
+    env_allow: [PATH]
+args:
+  - key: --address
+    expr: ['{{address}}']
+config_alias:
+  - key: address
+    expr: ['{{host}}:{{port}}']
+programs:
+  - name: worker
+    binary: worker.sh
+    depends_on: [app]
+    args:
+      - key: --queue
+        expr: ['{{queue}}']
+attach:
+  key: value
+profiles:
+  prod:
+    log:
+      console:
+        level: WARN
+init:
+  enabled: true
+  forward_signals: [USR1, 28]
+hot_reload:
+  enabled: true
+  debounce: 2s
+"#,
+    )
+    .unwrap();
+    let schema = config_schema();
+    assert_in_sync("", &schema, &serde_json::to_value(&config).unwrap());
+    // 默认值与 serde 默认值一致
+    assert_eq!(
+        schema["properties"]["project"]["properties"]["restart_policy"]["default"],
+        json!("ALWAYS")
+    );
+    assert_eq!(
+        schema["properties"]["log"]["properties"]["file"]["properties"]["path"]["default"],
+        json!(config.log.file.path)
+    );
+}
+
+#[test]
+fn config_schema_default_config_test() {
+    // 仓库中的默认配置未配置的可选字段均为 null
+    let config: ProjectConfig = serde_yaml::from_str(include_str!("../../config.yaml")).unwrap();
+    let schema = config_schema();
+    assert_in_sync("", &schema, &serde_json::to_value(&config).unwrap());
+    let config: ProjectConfig = serde_yaml::from_str("{}").unwrap();
+    assert_in_sync("", &schema, &serde_json::to_value(&config).unwrap());
+}
+
+#[test]
+fn config_schema_enum_test() {
+    // 新增枚举值时以下匹配会编译失败，提醒同步更新 schema 中的枚举列表
+    for level in LOGGER_LEVELS {
+        match level {
+            LoggerLevel::TRACE
+            | LoggerLevel::DEBUG
+            | LoggerLevel::INFO
+            | LoggerLevel::WARN
+            | LoggerLevel::ERROR
+            | LoggerLevel::NONE => {}
+        }
+    }
+    for mode in SOURCE_KEY_MODES {
+        match mode {
+            SourceKeyMode::ARG | SourceKeyMode::ENV => {}
+        }
+    }
+    for action in SIGHUP_ACTIONS {
+        match action {
+            SighupAction::RESTART | SighupAction::RELOAD => {}
+        }
+    }
+    for timezone in SCHEDULE_TIMEZONES {
+        match timezone {
+            ScheduleTimezone::LOCAL | ScheduleTimezone::UTC => {}
+        }
+    }
+    for action in WATCHDOG_ACTIONS {
+        match action {
+            WatchdogAction::LOG
+            | WatchdogAction::SCRIPT
+            | WatchdogAction::SIGNAL
+            | WatchdogAction::RESTART => {}
+        }
+    }
+    for class in EXIT_CLASSES {
+        match class {
+            ExitClass::SUCCESS | ExitClass::FAILURE | ExitClass::FATAL => {}
+        }
+    }
+    for policy in RESTART_POLICIES {
+        match policy {
+            RestartPolicy::NONE | RestartPolicy::ALWAYS | RestartPolicy::FAIL => {}
+        }
+    }
+    for class in IO_CLASSES {
+        match class {
+            IoClass::REALTIME | IoClass::BEST_EFFORT | IoClass::IDLE => {}
+        }
+    }
+    for mode in OUTPUT_MODES {
+        match mode {
+            OutputMode::PASSTHROUGH
+            | OutputMode::CAPTURE
+            | OutputMode::BOTH
+            | OutputMode::DISCARD => {}
+        }
+    }
+    let schema = config_schema();
+    let variants = schema["properties"]["project"]["properties"]["restart_policy"]["enum"]
+        .as_array()
+        .unwrap();
+    for variant in variants {
+        let policy: RestartPolicy = serde_json::from_value(variant.clone()).unwrap();
+        assert!(RESTART_POLICIES.contains(&policy));
+    }
+}
@@ -20,28 +20,39 @@
  * SOFTWARE.
  */
 
-use std::cell::Cell;
+use std::collections::HashMap;
+use std::env;
+use std::fmt::{Debug, Display, Formatter};
 use std::ops::Not;
-use std::thread;
-use std::time::Duration;
+use std::path::PathBuf;
+use std::process;
+use std::time::{Duration, Instant};
 
-use libc::{SIGHUP, SIGINT, SIGTERM};
+use libc::{c_int, SIGCHLD, SIGHUP, SIGINT, SIGTERM};
 
 use crate::args::soft_args::SoftArgs;
-use crate::binary::args_builder::load_context;
+use crate::args::soft_args::SoftCommand::{EXEC, LINT, SCHEMA};
+use crate::binary::args_builder::{load_context, load_variables, local_sources, BinaryContext};
 use crate::config::args;
-use crate::config::project_conf::load_info;
-use crate::config::prop::RestartPolicy::{FAIL, NONE};
-use crate::log::{debug_str, error_str, info_str};
-use crate::utils::command::execute_script;
-use crate::utils::file::new_temp_path;
+use crate::config::lint::{lint, lint_config};
+use crate::config::project_conf::{load_info, start_order};
+use crate::config::prop::{LoggerLevel, ProjectConfig, ProjectInfo};
+use crate::config::schema::config_schema;
+use crate::lib::SoftError;
+use crate::log::{debug, debug_str, error, info, info_str, warn};
+use crate::utils::command::{exec_program, ExecPolicy};
+use crate::utils::event::Notifier;
 use crate::utils::log;
-use crate::utils::log::{log_default, log_init};
+use crate::utils::log::{log_chown, log_default, log_init, log_truncate};
+use crate::utils::process::{reap_orphans, set_child_subreaper};
+use crate::utils::signal::{is_valid_signal, signal_name};
 use crate::utils::signal_hook::UnixSignalHook;
-use crate::utils::string::replace_all_str_from_map;
-use crate::worker::binary_worker::CallbackAction::EXITED;
-use crate::worker::binary_worker::{HookScripts, StableWorker};
-use crate::worker::script_worker::ScriptWorker;
+use crate::utils::user::Credential;
+use crate::utils::watch::FileWatcher;
+use crate::worker::supervisor::{
+    DependState, ProgramSpec, ProgramSupervisor, EXIT_BEFORE_SCRIPT, EXIT_CONFIG, EXIT_OSERR,
+    EXIT_SOFTWARE, EXIT_START_LIMIT,
+};
 
 mod binary;
 mod config;
@@ -49,175 +60,439 @@ mod lib;
 mod utils;
 mod worker;
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+fn main() {
+    let code = match run() {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            // 未标记退出码的错误均来自配置文件、启动参数或执行环境的配置
+            e.downcast_ref::<ExitError>().map_or(EXIT_CONFIG, |e| e.0)
+        }
+    };
+    process::exit(code);
+}
+
+/**
+带退出码的启动错误，用于区分配置错误与运行环境的错误
+ */
+struct ExitError(i32, SoftError);
+
+impl Display for ExitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.1, f)
+    }
+}
+
+impl Debug for ExitError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Debug::fmt(&self.1, f)
+    }
+}
+
+impl std::error::Error for ExitError {}
+
+/**
+运行守护进程并返回退出码：程序按策略结束时为第一个异常退出的程序的退出码 (被信号结束时为 128 + 信号量)，收到停止信号时为 0
+ */
+fn run() -> Result<i32, Box<dyn std::error::Error>> {
     info_str("项目已经启动.");
     let args = SoftArgs::parse(); // 拉取参数
-    let mut soft_config = load_info(&args.config_path, &args.variable)?; // 加载系统配置
-    soft_config.log.console.level = args.log_level;
-    log_init(&soft_config);
-    let data = load_context(&soft_config)?; // 载入并校验可用的参数
-    let signal_hook = UnixSignalHook::new(vec![SIGINT, SIGTERM, SIGHUP]);
-    replace_all_str_from_map(&mut soft_config.project.before_script, &data.script_vars);
-    replace_all_str_from_map(&mut soft_config.project.after_script, &data.script_vars);
-    replace_all_str_from_map(
-        &mut soft_config.project.check_health.script,
-        &data.script_vars,
-    );
-    replace_all_str_from_map(
-        &mut soft_config.project.check_started.script,
-        &data.script_vars,
-    );
-    // 脚本内容替换
-    let stable_worker = StableWorker::new(
-        soft_config.project.binary.to_owned(),
-        data.args.clone(),
-        data.envs.clone(),
-        &soft_config.project.signals,
-        HookScripts {
-            script_worker: soft_config.project.script_worker.clone(),
-            before_script: Some(soft_config.project.before_script).filter(|e| e.is_empty().not()),
-            after_script: Some(soft_config.project.after_script).filter(|e| e.is_empty().not()),
-        },
-    ); // 主要进程工作区
-
-    let health_check = Some(&soft_config.project.check_health.script)
-        .filter(|_| soft_config.project.check_health.interval != 0)
-        .filter(|e| e.is_empty().not())
-        .map(|e| {
-            ScriptWorker::new(
-                "健康检查任务",
-                &soft_config.project.script_worker,
-                e,
-                &data.envs,
-                soft_config.project.check_health.delay,
-                soft_config.project.check_health.interval,
-            )
-        })
-        .filter(|e| e.is_ok())
-        .map(|e| e.unwrap());
-    let started_check = Some(&soft_config.project.check_started.script)
-        .filter(|_| soft_config.project.check_started.interval != 0)
-        .filter(|e| e.is_empty().not())
-        .map(|e| {
-            ScriptWorker::new(
-                "启动完成检查任务",
-                &soft_config.project.script_worker,
-                e,
-                &data.envs,
-                0,
-                soft_config.project.check_started.interval,
-            )
-        })
-        .filter(|e| e.is_ok())
-        .map(|e| e.unwrap());
-    stable_worker.start();
-    // 项目启动完成。
-    let started_success: Cell<i32> = Cell::new(0);
-    let health_fail: Cell<i32> = Cell::new(0);
-    let enable_check = || -> () {
-        debug_str("开始重置");
-        started_success.set(0);
-        health_fail.set(0);
-        if let Some(started_check) = &started_check {
-            started_check.start()
-        }
-        if let Some(health_check) = &health_check {
-            health_check.start()
+    match args.command {
+        Some(SCHEMA) => {
+            let schema = serde_json::to_string_pretty(&config_schema())
+                .map_err(|e| ExitError(EXIT_SOFTWARE, SoftError::AppError(e.to_string())))?;
+            println!("{}", schema);
+            return Ok(0);
         }
+        Some(LINT) => {
+            let issues = lint(&args.config_path, &args.variable, &args.profiles)?;
+            for issue in &issues {
+                println!("{}", issue);
+            }
+            println!("共发现 {} 个问题.", issues.len());
+            if issues.iter().any(|e| e.level == LoggerLevel::ERROR) {
+                return Ok(EXIT_CONFIG);
+            }
+            return Ok(0);
+        }
+        Some(EXEC) | None => {}
+    }
+    let (mut soft_config, config_files) =
+        load_info(&args.config_path, &args.variable, &args.profiles)?; // 加载系统配置
+    if let Some(level) = args.log_level {
+        soft_config.log.console.level = level;
+    }
+    log_truncate(&soft_config);
+    // 运行用户在加载配置后才能确定，启动时在创建程序后再调整日志文件的所有者
+    log_init(&soft_config, None);
+    let vars =
+        load_variables(&soft_config).map_err(|e| SoftError::AppError(log::mask(&e.to_string())))?;
+    if let (Some(EXEC), false) = (&args.command, args.dry_run) {
+        let (code, e) = exec(&soft_config, &vars);
+        eprintln!("Error: {:?}", e);
+        return Ok(code);
+    }
+    let notifier = Notifier::new();
+    let mut supervisors: Vec<ProgramSupervisor> = vec![];
+    for program in &soft_config.programs {
+        let data = load_context(&soft_config, &vars, &program.args)
+            .map_err(|e| SoftError::AppError(log::mask(&e.to_string())))?; // 载入并校验可用的参数
+        if args.dry_run {
+            print_dry_run(&program.project, &data)?;
+            continue;
+        }
+        let name = &program.project.name;
+        let spec = ProgramSpec::new(program, data)
+            .map_err(|e| SoftError::AppError(format!("[{}] {}", name, e)))?;
+        let supervisor = ProgramSupervisor::new(spec, &notifier)
+            .map_err(|e| ExitError(EXIT_OSERR, SoftError::AppError(format!("[{}] {}", name, e))))?;
+        supervisors.push(supervisor);
+    }
+    if args.dry_run {
+        return Ok(0);
+    }
+    // 文件日志的所有者与第一个程序的运行用户一致
+    let project = &soft_config.programs[0].project;
+    if let Some(credential) =
+        Credential::resolve(&project.user, &project.group, &project.supplementary_groups)?
+    {
+        log_chown(&soft_config, &credential);
+    }
+    let mut order = start_order(&soft_config.programs)?;
+    let mut watcher = config_watcher(&soft_config, &config_files, &notifier);
+    let mut reload_at: Option<Instant> = None;
+    if set_child_subreaper().not() {
+        warn("无法设置为子进程收割者，脱离进程组的后代进程可能无法被清理.".to_string());
+    }
+    // 作为容器入口运行时自动启用 init 模式
+    let init = args.init || soft_config.init.enabled || unsafe { libc::getpid() } == 1;
+    let forward = if init {
+        forward_signals(&soft_config)
+    } else {
+        vec![]
     };
+    if init {
+        info(format!(
+            "以 init 模式运行，转发信号: {:?}",
+            forward.iter().map(|e| signal_name(*e)).collect::<Vec<_>>()
+        ));
+    }
+    // SIGCHLD 仅用于唤醒主循环回收孤儿进程
+    let mut hooked = HANDLED_SIGNALS.to_vec();
+    hooked.extend(&forward);
+    let signal_hook = UnixSignalHook::new(hooked, &STOP_SIGNALS, &notifier);
+    let mut launched: Vec<usize> = vec![];
+    // 全部程序均已按策略结束
+    let mut finished = false;
     loop {
-        if let Some(health_check) = &health_check {
-            for x in health_check.get_status() {
-                if x == 0 {
-                    health_fail.set(0);
-                } else {
-                    health_fail.set(health_fail.get() + 1);
-                }
+        let seen = notifier.current();
+        // 依赖的程序全部启动完成后才启动当前程序，依赖已失败或未启动完成即结束时不再启动
+        for index in &order {
+            if supervisors[*index].is_launched() || supervisors[*index].is_finished() {
+                continue;
             }
-            if health_fail.get() >= soft_config.project.check_health.failures as i32 {
-                error_str("健康检查失败！主进程将重启。");
-                health_check.stop();
-                stable_worker.restart();
-                enable_check();
+            let depends: Vec<(&String, DependState)> = supervisors[*index]
+                .depends_on
+                .iter()
+                .map(|depend| {
+                    let state = supervisors
+                        .iter()
+                        .find(|e| &e.name == depend)
+                        .map_or(DependState::WAITING, |e| e.depend_state());
+                    (depend, state)
+                })
+                .collect();
+            match DependState::all(depends.iter().map(|(_, state)| *state)) {
+                DependState::READY => {
+                    supervisors[*index].launch();
+                    launched.push(*index);
+                }
+                DependState::BROKEN => {
+                    let depend = depends
+                        .iter()
+                        .find(|(_, state)| *state == DependState::BROKEN)
+                        .map(|(name, _)| name.to_string())
+                        .unwrap_or_default();
+                    supervisors[*index].skip(&depend);
+                }
+                DependState::WAITING => {}
             }
         }
-        if let Some(started_check) = &started_check {
-            for x in started_check.get_status() {
-                if x == 0 {
-                    started_success.set(started_success.get() + 1);
-                } else {
-                    started_success.set(0);
-                }
+        for supervisor in supervisors.iter_mut() {
+            supervisor.tick();
+        }
+        reap_orphans();
+
+        // 配置文件变化后等待 debounce 再重新加载
+        if let Some(watcher) = &watcher {
+            let changed = watcher.changed();
+            if changed.is_empty().not() {
+                debug(format!("配置文件发生变化: {:?}", changed));
+                reload_at = Some(Instant::now() + soft_config.hot_reload.debounce.0);
             }
-            if started_success.get() >= soft_config.project.check_started.success as i32
-                && started_success.get() != -1
-            {
-                started_check.stop();
-                info_str("启动成功！回调脚本");
-                let status = execute_script(
-                    "启动成功回调钩子",
-                    &soft_config.project.script_worker,
-                    &soft_config.project.check_started.started_script,
-                    &data.envs,
-                );
-                if let Ok(code) = status {
-                    if code == 0 {
-                        info_str("启动检测回调执行完成。")
-                    } else {
-                        error_str("启动检测回调执行失败。")
-                    }
-                } else {
-                    error_str("启动检测回调执行失败。")
+        }
+        if reload_at.filter(|e| Instant::now() >= *e).is_some() {
+            reload_at = None;
+            match reload_config(&args, &soft_config, &mut supervisors) {
+                Ok((config, files)) => {
+                    order = start_order(&config.programs)?;
+                    watcher = config_watcher(&config, &files, &notifier);
+                    soft_config = config;
                 }
-                started_success.set(-1);
+                Err(e) => error(format!(
+                    "配置重新加载失败，继续使用原配置: {}",
+                    log::mask(&e.to_string())
+                )),
             }
         }
 
         let signals = signal_hook.signals().to_vec();
+        for signal in signals.iter().filter(|e| forward.contains(e)) {
+            for supervisor in &supervisors {
+                supervisor.forward(*signal);
+            }
+        }
         if signals.contains(&SIGINT) || signals.contains(&SIGTERM) {
             // 收到停止命令，开始停止
             debug_str("发现 SIGINT");
             break;
         } else if signals.contains(&SIGHUP) {
             debug_str("发现 SIGHUP");
-            stable_worker.restart();
-            enable_check();
-        }
-        if let Ok(nonblock) = stable_worker.status.try_lock() {
-            if let EXITED(exit_code) = *nonblock {
-                if (exit_code == 0 && soft_config.project.restart_policy == FAIL)
-                    || soft_config.project.restart_policy == NONE
-                {
-                    debug_str("主进程已结束，根据策略,项目已结束.");
-                    stable_worker.exit();
-                    break;
-                } else if exit_code != 0 && soft_config.project.restart_policy == FAIL {
-                    debug_str("主进程异常退出，根据策略,项目将重启.");
-                    stable_worker.restart();
-                    enable_check();
-                } else {
-                    debug_str("主进程已退出，根据策略,项目将重启.");
-                    stable_worker.restart();
-                    enable_check();
-                }
-            };
+            for supervisor in supervisors.iter_mut() {
+                supervisor.hangup();
+            }
+        }
+        if supervisors.iter().any(|e| e.is_failed()) {
+            break;
+        }
+        if supervisors.iter().all(|e| e.is_finished()) {
+            debug_str("全部程序已结束.");
+            finished = true;
+            break;
         }
 
-        thread::sleep(Duration::from_millis(500));
+        // 等待程序状态变化、信号或下次需要处理的时间
+        let deadline = Instant::now() + Duration::from_secs(30);
+        let deadline = supervisors
+            .iter()
+            .filter_map(|e| e.next_deadline())
+            .chain(reload_at)
+            .fold(deadline, |a, b| a.min(b));
+        notifier.wait(seen, deadline);
+    }
+    // 按启动顺序的逆序停止程序，停止期间继续向尚未停止的程序转发信号
+    for (position, index) in launched.iter().enumerate().rev() {
+        let pids = launched[..=position]
+            .iter()
+            .filter_map(|e| supervisors[*e].pid())
+            .collect();
+        signal_hook.shutdown(&forward, pids);
+        for signal in signal_hook.signals() {
+            if forward.contains(&signal) {
+                supervisors[*index].forward(signal);
+            }
+        }
+        supervisors[*index].shutdown();
     }
-    stable_worker.exit();
-    debug_str("等待主工作线程退出.");
     signal_hook.close();
-    for x in health_check {
-        debug_str("等待健康检测脚本停止...");
-        x.close();
-        x.wait_closed();
+    if supervisors.iter().any(|e| e.is_failed()) {
+        return Ok(EXIT_START_LIMIT);
+    }
+    if init {
+        // 与 tini 相同，以第一个程序的退出码退出
+        return Ok(supervisors[0].exit_code().unwrap_or(0));
+    }
+    if finished {
+        // 以第一个异常退出的程序的退出码退出，success_exit_codes 中的退出码视为正常退出
+        return Ok(supervisors
+            .iter()
+            .find_map(|e| e.failure_exit_code().filter(|e| *e != 0))
+            .unwrap_or(0));
+    }
+    Ok(0)
+}
+
+/// 启用热重载时监听配置文件、`include` 引用的文件与本地配置来源
+fn config_watcher(
+    soft_config: &ProjectConfig,
+    config_files: &[PathBuf],
+    notifier: &Notifier,
+) -> Option<FileWatcher> {
+    if soft_config.hot_reload.enabled.not() {
+        return None;
+    }
+    let mut files = config_files.to_vec();
+    files.extend(local_sources(soft_config));
+    match FileWatcher::new(&files, &[], notifier) {
+        Ok(watcher) => Some(watcher),
+        Err(e) => {
+            warn(format!("无法监听配置文件，热重载不会生效: {}", e));
+            None
+        }
+    }
+}
+
+/**
+重新加载配置：全部程序的参数解析与执行环境校验通过后才会应用，否则保留原配置
+
+程序列表变化与 `init` 的变更需要重启 args-tools 才能生效
+ */
+fn reload_config(
+    args: &SoftArgs,
+    current: &ProjectConfig,
+    supervisors: &mut [ProgramSupervisor],
+) -> Result<(ProjectConfig, Vec<PathBuf>), SoftError> {
+    info_str("配置文件已变化，开始重新加载配置.");
+    let (mut soft_config, config_files) =
+        load_info(&args.config_path, &args.variable, &args.profiles)?;
+    if let Some(level) = args.log_level {
+        soft_config.log.console.level = level;
+    }
+    let errors: Vec<String> = lint_config(&soft_config, &HashMap::new())
+        .iter()
+        .filter(|e| e.level == LoggerLevel::ERROR)
+        .map(|e| e.to_string())
+        .collect();
+    if errors.is_empty().not() {
+        return Err(SoftError::AppError(errors.join("; ")));
+    }
+    let names = |config: &ProjectConfig| -> Vec<String> {
+        config
+            .programs
+            .iter()
+            .map(|e| e.project.name.to_string())
+            .collect()
+    };
+    if names(&soft_config) != names(current) {
+        return Err(SoftError::AppError(
+            "程序列表发生变化，需要重启 args-tools 才能生效".to_string(),
+        ));
+    }
+    let vars =
+        load_variables(&soft_config).map_err(|e| SoftError::AppError(log::mask(&e.to_string())))?;
+    let mut specs = vec![];
+    for program in &soft_config.programs {
+        let name = &program.project.name;
+        let spec = load_context(&soft_config, &vars, &program.args)
+            .and_then(|context| ProgramSpec::new(program, context))
+            .map_err(|e| {
+                SoftError::AppError(format!("[{}] {}", name, log::mask(&e.to_string())))
+            })?;
+        specs.push(spec);
+    }
+    if soft_config.init != current.init {
+        warn("init 配置的变更需要重启 args-tools 才能生效.".to_string());
+    }
+    let owner = |config: &ProjectConfig| {
+        let project = &config.programs[0].project;
+        (
+            project.user.to_string(),
+            project.group.to_string(),
+            project.supplementary_groups.clone(),
+        )
+    };
+    if soft_config.log != current.log || owner(&soft_config) != owner(current) {
+        // 重新打开的日志文件可能是新建的，需要再次调整所有者
+        let (user, group, groups) = owner(&soft_config);
+        let credential = Credential::resolve(&user, &group, &groups)?;
+        log_init(&soft_config, credential.as_ref());
+    }
+    for (supervisor, spec) in supervisors.iter_mut().zip(specs) {
+        supervisor.update(spec);
+    }
+    info_str("配置已重新加载.");
+    Ok((soft_config, config_files))
+}
+
+/// 主循环自行处理的信号量，不会被转发
+const HANDLED_SIGNALS: [c_int; 4] = [SIGINT, SIGTERM, SIGHUP, SIGCHLD];
+
+/// 停止信号，开始停止后再次收到时执行默认动作以强制退出
+const STOP_SIGNALS: [c_int; 2] = [SIGINT, SIGTERM];
+
+/// init 模式下需要转发的信号量，忽略主循环自行处理与无法捕获的信号量
+fn forward_signals(soft_config: &ProjectConfig) -> Vec<c_int> {
+    let mut forward: Vec<c_int> = vec![];
+    for signal in soft_config.init.forward_signals.iter().map(|e| e.0) {
+        if HANDLED_SIGNALS.contains(&signal)
+            || signal_hook::consts::FORBIDDEN.contains(&signal)
+            || is_valid_signal(signal).not()
+        {
+            warn(format!(
+                "信号量 {} 无法被转发，已忽略.",
+                signal_name(signal)
+            ));
+        } else if forward.contains(&signal).not() {
+            forward.push(signal);
+        }
+    }
+    forward
+}
+
+/**
+以 exec 方式启动程序，只在失败时返回，同时返回对应的退出码。
+
+exec 模式只支持一个程序，不创建监控线程，`after_script`、检查脚本与 cgroup 不会生效
+ */
+fn exec(soft_config: &ProjectConfig, vars: &HashMap<String, String>) -> (i32, SoftError) {
+    let program = match soft_config.programs.as_slice() {
+        [program] => program,
+        _ => {
+            return (
+                EXIT_CONFIG,
+                SoftError::AppError("exec 模式只支持一个程序".to_string()),
+            )
+        }
+    };
+    let project = &program.project;
+    if project.cgroup.enabled {
+        warn(format!("[{}] exec 模式下 cgroup 不会生效", project.name));
+    }
+    let result = load_context(soft_config, vars, &program.args)
+        .map_err(|e| SoftError::AppError(log::mask(&e.to_string())))
+        .and_then(|context| {
+            let policy = ExecPolicy::program(project, &context)?;
+            let script_policy = ExecPolicy::script(project, &context)?;
+            Ok(exec_program(project, &context, &policy, &script_policy))
+        });
+    match result {
+        Ok(Ok(code)) => (
+            EXIT_BEFORE_SCRIPT,
+            SoftError::AppError(format!(
+                "[{}] 前置钩子执行失败，返回码为 {}",
+                project.name, code
+            )),
+        ),
+        Ok(Err(e)) => (EXIT_OSERR, e),
+        Err(e) => (EXIT_CONFIG, e),
+    }
+}
+
+/// 输出解析后的启动信息，敏感内容已被遮盖
+fn print_dry_run(project: &ProjectInfo, data: &BinaryContext) -> Result<(), SoftError> {
+    let policy = ExecPolicy::program(project, data)?;
+    println!("程序: {}", project.name);
+    println!("可执行文件: {}", project.binary);
+    if let Some(workdir) = &policy.workdir {
+        println!("工作目录: {}", workdir.display());
+    }
+    if let Some(credential) = &policy.credential {
+        println!(
+            "运行用户: {} (uid={}, gid={}, groups={:?})",
+            credential.name, credential.uid, credential.gid, credential.groups
+        );
     }
-    for x in started_check {
-        debug_str("等待启动检测脚本停止...");
-        x.close();
-        x.wait_closed();
+    println!("启动参数: {}", log::mask(&format!("{:?}", data.args)));
+    // 仅输出与当前进程不同的环境变量
+    println!("环境变量:");
+    let mut envs: Vec<(&String, &String)> = policy
+        .envs
+        .iter()
+        .filter(|(key, value)| env::var(key).ok().as_ref() != Some(value))
+        .collect();
+    envs.sort();
+    for (key, value) in envs {
+        println!("  {}={}", key, log::mask(value));
     }
-    stable_worker.wait_exited();
     Ok(())
 }
@@ -20,8 +20,16 @@
  * SOFTWARE.
  */
 
+pub mod cgroup;
 pub mod command;
+pub mod cron;
+pub mod event;
 pub mod file;
 pub mod log;
+pub mod process;
+pub mod resource;
+pub mod signal;
 pub mod signal_hook;
 pub mod string;
+pub mod user;
+pub mod watch;
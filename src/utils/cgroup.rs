@@ -0,0 +1,261 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! cgroup v2：为每个程序创建独立的 cgroup，设置资源上限、统计 OOM 并在停止时清理残留进程
+//!
+//! 默认在当前进程所在的 cgroup 下创建，要求该子树已委派给当前用户。由于 cgroup v2
+//! 不允许非根 cgroup 同时包含进程与启用控制器的子 cgroup，当前进程会先移入 `supervisor` 子 cgroup。
+
+use std::ffi::CString;
+use std::fs;
+use std::io;
+use std::ops::Not;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use crate::config::prop::CgroupConfig;
+use crate::lib::SoftError;
+use crate::log::{debug, warn};
+
+const CONTROLLERS: [&str; 3] = ["memory", "cpu", "pids"];
+
+/// 已初始化的父 cgroup，多个程序共用
+static PARENTS: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// 程序的 cgroup
+#[derive(Debug, Clone)]
+pub struct Cgroup {
+    path: PathBuf,
+    procs: CString,
+}
+
+impl Cgroup {
+    /**
+    为程序创建 cgroup 并写入资源上限，未启用时返回 `None`
+     */
+    pub fn create(name: &str, config: &CgroupConfig) -> Result<Option<Cgroup>, SoftError> {
+        if config.enabled.not() {
+            return Ok(None);
+        }
+        let root = cgroup_root().ok_or_else(|| {
+            SoftError::AppError("未找到 cgroup v2 挂载点，无法启用 cgroup".to_string())
+        })?;
+        let parent = match config.parent.trim_matches('/') {
+            "" => current_cgroup(&root)?,
+            parent => root.join(parent),
+        };
+        prepare_parent(&parent)?;
+        let path = parent.join(name);
+        if path.is_dir().not() {
+            fs::create_dir(&path)
+                .map_err(|e| SoftError::AppError(format!("无法创建 cgroup {:?}: {}", path, e)))?;
+        }
+        let cgroup = Cgroup {
+            procs: CString::new(path.join("cgroup.procs").as_os_str().as_bytes()).unwrap(),
+            path,
+        };
+        for (file, value) in [
+            ("memory.max", &config.memory_max),
+            ("cpu.max", &config.cpu_max),
+            ("pids.max", &config.pids_max),
+        ] {
+            if value.is_empty() {
+                continue;
+            }
+            fs::write(cgroup.path.join(file), value).map_err(|e| {
+                SoftError::AppError(format!(
+                    "无法写入 cgroup 配置 {:?} = {}: {}",
+                    cgroup.path.join(file),
+                    value,
+                    e
+                ))
+            })?;
+        }
+        debug(format!("[{}] 使用 cgroup {:?}", name, cgroup.path));
+        Ok(Some(cgroup))
+    }
+
+    /// 在 `pre_exec` 中将当前进程移入 cgroup，只调用异步信号安全的系统调用
+    pub fn join(&self) -> io::Result<()> {
+        unsafe {
+            let fd = libc::open(self.procs.as_ptr(), libc::O_WRONLY | libc::O_CLOEXEC);
+            if fd < 0 {
+                return Err(io::Error::last_os_error());
+            }
+            // 写入 0 表示移动写入者本身
+            let written = libc::write(fd, b"0".as_ptr() as *const libc::c_void, 1);
+            libc::close(fd);
+            if written < 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// 被 OOM Killer 结束的进程数量
+    pub fn oom_kills(&self) -> u64 {
+        fs::read_to_string(self.path.join("memory.events"))
+            .map(|e| parse_oom_kills(&e))
+            .unwrap_or(0)
+    }
+
+    /// cgroup 中剩余的进程
+    pub fn procs(&self) -> Vec<libc::pid_t> {
+        fs::read_to_string(self.path.join("cgroup.procs"))
+            .map(|e| e.lines().filter_map(|e| e.trim().parse().ok()).collect())
+            .unwrap_or_default()
+    }
+
+    /**
+    结束 cgroup 中剩余的全部进程，内核不支持 `cgroup.kill` 时逐个发送 SIGKILL
+     */
+    pub fn kill(&self, name: &str) {
+        let procs = self.procs();
+        if procs.is_empty() {
+            return;
+        }
+        warn(format!(
+            "[{}] cgroup 中仍有 {} 个残留进程，强制结束: {:?}",
+            name,
+            procs.len(),
+            procs
+        ));
+        if fs::write(self.path.join("cgroup.kill"), "1").is_err() {
+            for pid in &procs {
+                unsafe { libc::kill(*pid, libc::SIGKILL) };
+            }
+        }
+        for _ in 0..50 {
+            if self.procs().is_empty() {
+                return;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+        warn(format!("[{}] cgroup 中的残留进程未能全部结束", name));
+    }
+
+    /// 删除 cgroup，cgroup 中仍有进程时删除失败
+    pub fn remove(&self) {
+        if let Err(e) = fs::remove_dir(&self.path) {
+            debug(format!("无法删除 cgroup {:?}: {}", self.path, e));
+        }
+    }
+}
+
+/// cgroup v2 的挂载点，通常为 `/sys/fs/cgroup`
+fn cgroup_root() -> Option<PathBuf> {
+    fs::read_to_string("/proc/self/mounts")
+        .ok()?
+        .lines()
+        .map(|e| e.split_whitespace().collect::<Vec<&str>>())
+        .find(|e| e.len() > 2 && e[2] == "cgroup2")
+        .map(|e| PathBuf::from(e[1]))
+}
+
+/// 当前进程所在的 cgroup
+fn current_cgroup(root: &Path) -> Result<PathBuf, SoftError> {
+    let content = fs::read_to_string("/proc/self/cgroup")?;
+    let path = content
+        .lines()
+        .find_map(|e| e.strip_prefix("0::"))
+        .ok_or_else(|| SoftError::AppError("无法确定当前进程所在的 cgroup".to_string()))?;
+    let path = root.join(path.trim().trim_start_matches('/'));
+    // 已经移入 supervisor 子 cgroup 时使用其父 cgroup
+    if path.file_name().map(|e| e == "supervisor").unwrap_or(false) {
+        if let Some(parent) = path.parent() {
+            if PARENTS
+                .lock()
+                .map(|e| e.contains(&parent.to_path_buf()))
+                .unwrap_or(false)
+            {
+                return Ok(parent.to_path_buf());
+            }
+        }
+    }
+    Ok(path)
+}
+
+/**
+准备父 cgroup：将其中的当前进程移入 `supervisor` 子 cgroup，并为子 cgroup 启用控制器
+ */
+fn prepare_parent(parent: &Path) -> Result<(), SoftError> {
+    let mut parents = PARENTS.lock().unwrap();
+    if parents.contains(&parent.to_path_buf()) {
+        return Ok(());
+    }
+    fs::create_dir_all(parent)
+        .map_err(|e| SoftError::AppError(format!("无法创建 cgroup {:?}: {}", parent, e)))?;
+    let pid = unsafe { libc::getpid() };
+    let procs = fs::read_to_string(parent.join("cgroup.procs")).unwrap_or_default();
+    if procs.lines().any(|e| e.trim() == pid.to_string()) {
+        let supervisor = parent.join("supervisor");
+        if supervisor.is_dir().not() {
+            fs::create_dir(&supervisor).map_err(|e| {
+                SoftError::AppError(format!("无法创建 cgroup {:?}: {}", supervisor, e))
+            })?;
+        }
+        fs::write(supervisor.join("cgroup.procs"), pid.to_string()).map_err(|e| {
+            SoftError::AppError(format!("无法将当前进程移入 cgroup {:?}: {}", supervisor, e))
+        })?;
+    }
+    let available = fs::read_to_string(parent.join("cgroup.controllers")).unwrap_or_default();
+    for controller in CONTROLLERS {
+        if available.split_whitespace().any(|e| e == controller).not() {
+            warn(format!(
+                "cgroup {:?} 不支持 {} 控制器，相关限制不会生效",
+                parent, controller
+            ));
+            continue;
+        }
+        if let Err(e) = fs::write(
+            parent.join("cgroup.subtree_control"),
+            format!("+{}", controller),
+        ) {
+            warn(format!(
+                "无法在 cgroup {:?} 中启用 {} 控制器: {}",
+                parent, controller, e
+            ));
+        }
+    }
+    parents.push(parent.to_path_buf());
+    Ok(())
+}
+
+fn parse_oom_kills(events: &str) -> u64 {
+    events
+        .lines()
+        .find_map(|line| {
+            line.strip_prefix("oom_kill ")
+                .and_then(|e| e.trim().parse().ok())
+        })
+        .unwrap_or(0)
+}
+
+#[test]
+fn parse_oom_kills_test() {
+    let events = "low 0\nhigh 0\nmax 12\noom 3\noom_kill 2\noom_group_kill 0\n";
+    assert_eq!(parse_oom_kills(events), 2);
+    assert_eq!(parse_oom_kills(""), 0);
+}
@@ -21,25 +21,235 @@
  */
 
 use std::collections::HashMap;
+use std::env;
 use std::fs;
+use std::io;
 use std::ops::Not;
+use std::os::unix::process::CommandExt;
+use std::path::{Path, PathBuf};
 use std::process::Command;
 
+use crate::binary::args_builder::BinaryContext;
+use crate::config::prop::ProjectInfo;
 use crate::lib::SoftError;
-use crate::{log, new_temp_path};
+use crate::log;
+use crate::utils::cgroup::Cgroup;
+use crate::utils::file::new_temp_path;
+use crate::utils::process::output_managed;
+use crate::utils::resource::ProcessLimits;
+use crate::utils::string::{glob_match, replace_all_str_from_map};
+use crate::utils::user::Credential;
+
+/**
+子进程的执行环境：工作目录、umask、运行用户与完整的环境变量
+ */
+#[derive(Debug, Clone)]
+pub struct ExecPolicy {
+    pub workdir: Option<PathBuf>,
+    pub umask: Option<u32>,
+    /// 资源限制与 cgroup，仅用于程序本身
+    pub limits: Option<ProcessLimits>,
+    pub cgroup: Option<Cgroup>,
+    pub credential: Option<Credential>,
+    pub envs: HashMap<String, String>,
+}
+
+impl ExecPolicy {
+    /**
+    程序的执行环境，未配置 `workdir` 时使用可执行文件所在目录
+     */
+    pub fn program(project: &ProjectInfo, context: &BinaryContext) -> Result<Self, SoftError> {
+        let binary_dir = Path::new(&project.binary)
+            .parent()
+            .unwrap_or(Path::new("/"));
+        let workdir = Some(&project.workdir)
+            .filter(|e| e.is_empty().not())
+            .map(|e| binary_dir.join(e))
+            .unwrap_or_else(|| binary_dir.to_path_buf());
+        let credential =
+            Credential::resolve(&project.user, &project.group, &project.supplementary_groups)?;
+        Ok(ExecPolicy {
+            workdir: Some(workdir),
+            umask: project.umask.map(|e| e.0),
+            limits: ProcessLimits::resolve(project)?,
+            // cgroup 在程序启动前由调用方创建
+            cgroup: None,
+            envs: build_envs(
+                project.clear_env,
+                &project.env_allow,
+                &project.env_deny,
+                &project.env,
+                credential.as_ref(),
+                context,
+            ),
+            credential,
+        })
+    }
+
+    /**
+    钩子与检查脚本的执行环境，`script_policy` 中未配置的项与程序相同，均未配置工作目录时使用当前目录
+     */
+    pub fn script(project: &ProjectInfo, context: &BinaryContext) -> Result<Self, SoftError> {
+        let policy = &project.script_policy;
+        let binary_dir = Path::new(&project.binary)
+            .parent()
+            .unwrap_or(Path::new("/"));
+        let mut static_envs = project.env.clone();
+        static_envs.extend(policy.env.clone());
+        let credential = Credential::resolve(
+            policy.user.as_ref().unwrap_or(&project.user),
+            policy.group.as_ref().unwrap_or(&project.group),
+            policy
+                .supplementary_groups
+                .as_ref()
+                .unwrap_or(&project.supplementary_groups),
+        )?;
+        Ok(ExecPolicy {
+            workdir: policy
+                .workdir
+                .as_ref()
+                .or(Some(&project.workdir))
+                .filter(|e| e.is_empty().not())
+                .map(|e| binary_dir.join(e)),
+            umask: policy.umask.or(project.umask).map(|e| e.0),
+            limits: None,
+            cgroup: None,
+            envs: build_envs(
+                policy.clear_env.unwrap_or(project.clear_env),
+                policy.env_allow.as_ref().unwrap_or(&project.env_allow),
+                policy.env_deny.as_ref().unwrap_or(&project.env_deny),
+                &static_envs,
+                credential.as_ref(),
+                context,
+            ),
+            credential,
+        })
+    }
+
+    /// 将执行环境应用到命令上
+    pub fn apply<'a>(&self, command: &'a mut Command) -> &'a mut Command {
+        command.env_clear().envs(&self.envs);
+        if let Some(workdir) = &self.workdir {
+            command.current_dir(workdir);
+        }
+        if let Some(umask) = self.umask {
+            unsafe {
+                command.pre_exec(move || {
+                    libc::umask(umask as libc::mode_t);
+                    Ok(())
+                });
+            }
+        }
+        // 移入 cgroup 与资源限制需要在降低权限之前进行
+        if let Some(cgroup) = self.cgroup.clone() {
+            unsafe {
+                command.pre_exec(move || cgroup.join());
+            }
+        }
+        if let Some(limits) = self.limits.clone() {
+            unsafe {
+                command.pre_exec(move || limits.apply());
+            }
+        }
+        if let Some(credential) = self.credential.clone() {
+            unsafe {
+                command.pre_exec(move || credential.drop_privileges());
+            }
+        }
+        command
+    }
+
+    /// 启动子进程失败时的错误信息，资源限制应用失败时说明失败的配置项
+    pub fn describe_error(&self, error: &io::Error) -> String {
+        self.limits
+            .as_ref()
+            .and_then(|e| e.describe(error))
+            .unwrap_or_else(|| error.to_string())
+    }
+
+    /// 将子进程需要读取的文件 (如临时脚本) 的所有者调整为运行用户
+    pub fn own(&self, path: &Path) {
+        if let Some(credential) = &self.credential {
+            if let Err(e) = credential.chown(path) {
+                log::warn(format!("无法调整 {:?} 的所有者: {}", path, e));
+            }
+        }
+    }
+}
+
+/**
+组装环境变量：继承的环境变量按 `clear_env`、`env_allow`、`env_deny` 过滤后，
+依次加入运行用户的 `USER`、`LOGNAME`、`HOME`，静态环境变量 (支持模板变量) 与参数中的环境变量
+ */
+fn build_envs(
+    clear_env: bool,
+    allow: &[String],
+    deny: &[String],
+    static_envs: &HashMap<String, String>,
+    credential: Option<&Credential>,
+    context: &BinaryContext,
+) -> HashMap<String, String> {
+    let mut envs: HashMap<String, String> = env::vars()
+        .filter(|_| clear_env.not())
+        .filter(|(key, _)| allow.is_empty() || allow.iter().any(|e| glob_match(e, key)))
+        .filter(|(key, _)| deny.iter().any(|e| glob_match(e, key)).not())
+        .collect();
+    if let Some(credential) = credential {
+        envs.insert("USER".to_string(), credential.name.to_string());
+        envs.insert("LOGNAME".to_string(), credential.name.to_string());
+        envs.insert("HOME".to_string(), credential.home.to_string());
+    }
+    for (key, value) in static_envs {
+        let mut value = value.to_string();
+        replace_all_str_from_map(&mut value, &context.script_vars);
+        envs.insert(key.to_string(), value);
+    }
+    envs.extend(context.envs.clone());
+    envs
+}
+
+/**
+执行启动前脚本后以程序替换当前进程 (`execve`)，程序保留当前进程号，成功时不会返回
+
+启动前脚本返回码不为 0 时返回 `Ok(返回码)`，脚本或程序无法执行时返回错误
+ */
+pub fn exec_program(
+    project: &ProjectInfo,
+    context: &BinaryContext,
+    policy: &ExecPolicy,
+    script_policy: &ExecPolicy,
+) -> Result<i32, SoftError> {
+    if project.before_script.is_empty().not() {
+        let mut script = project.before_script.clone();
+        replace_all_str_from_map(&mut script, &context.script_vars);
+        match execute_script("启动前钩子", &project.script_worker, &script, script_policy)? {
+            0 => {}
+            code => return Ok(code),
+        }
+    }
+    log::info(format!("[{}] 以 exec 方式启动程序.", project.name));
+    let error = policy
+        .apply(&mut Command::new(&project.binary))
+        .args(&context.args)
+        .exec();
+    Err(SoftError::AppError(format!(
+        "[{}] 程序启动错误！{}",
+        project.name,
+        policy.describe_error(&error)
+    )))
+}
 
 pub fn execute_script(
     name: &str,
     worker: &str,
     script: &str,
-    envs: &HashMap<String, String>,
+    policy: &ExecPolicy,
 ) -> Result<i32, SoftError> {
     let buf = new_temp_path("temp_script");
     fs::write(&buf, script)?;
-    let output = Command::new(worker.clone())
-        .arg(&buf.to_str().unwrap().to_string())
-        .envs(envs)
-        .output()?;
+    policy.own(&buf);
+    let output =
+        output_managed(policy.apply(Command::new(worker).arg(&buf.to_str().unwrap().to_string())))?;
     for x in
         Some(String::from_utf8_lossy(&output.stdout).to_string()).filter(|e| e.is_empty().not())
     {
@@ -0,0 +1,274 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+//! cron 表达式：`分 时 日 月 周` 五个字段，支持 `*`、`1,2`、`1-5`、`*/10`、`1-30/5`，
+//! 月份与星期可以使用英文缩写 (如 `JAN`、`MON`)，另支持 `@hourly`、`@daily`、`@weekly`、`@monthly` 与 `@yearly`
+
+use std::fmt::{Display, Formatter};
+use std::ops::Not;
+use std::str::FromStr;
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, NaiveDateTime, TimeZone, Timelike};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::lib::SoftError;
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// 查找下次触发时间时最多向后查找的天数
+const SEARCH_DAYS: u32 = 366 * 8;
+
+#[derive(PartialEq, Debug, Clone)]
+pub struct CronExpr {
+    /// 原始表达式
+    text: String,
+    minutes: u64,
+    hours: u64,
+    days: u64,
+    months: u64,
+    weekdays: u64,
+    /// 日与星期均被限制时，两者满足其一即可
+    day_or_weekday: bool,
+}
+
+impl FromStr for CronExpr {
+    type Err = SoftError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let text = s.trim();
+        let expanded = match text.to_ascii_lowercase().as_str() {
+            "@hourly" => "0 * * * *",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@weekly" => "0 0 * * 0",
+            "@monthly" => "0 0 1 * *",
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            _ => text,
+        };
+        let fields: Vec<&str> = expanded.split_whitespace().collect();
+        if fields.len() != 5 {
+            return Err(SoftError::AppError(format!(
+                "无效的 cron 表达式 {}，需要 5 个字段",
+                text
+            )));
+        }
+        let error = |e: String| SoftError::AppError(format!("无效的 cron 表达式 {}: {}", text, e));
+        let mut weekdays = parse_field(fields[4], 0, 7, &WEEKDAYS).map_err(error)?;
+        // 7 与 0 均表示星期日
+        if weekdays & (1 << 7) != 0 {
+            weekdays = (weekdays | 1) & !(1 << 7);
+        }
+        Ok(CronExpr {
+            text: text.to_string(),
+            minutes: parse_field(fields[0], 0, 59, &[]).map_err(error)?,
+            hours: parse_field(fields[1], 0, 23, &[]).map_err(error)?,
+            days: parse_field(fields[2], 1, 31, &[]).map_err(error)?,
+            months: parse_field(fields[3], 1, 12, &MONTHS).map_err(error)?,
+            weekdays,
+            day_or_weekday: fields[2].starts_with('*').not() && fields[4].starts_with('*').not(),
+        })
+    }
+}
+
+impl Display for CronExpr {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl Serialize for CronExpr {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.text)
+    }
+}
+
+impl<'de> Deserialize<'de> for CronExpr {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(|e: SoftError| serde::de::Error::custom(e.to_string()))
+    }
+}
+
+impl CronExpr {
+    /**
+    `now` 之后的下次触发时间，按 `now` 所在时区计算；
+    夏令时切换时不存在的时间被跳过，重复的时间只触发一次
+     */
+    pub fn next_after<Tz: TimeZone>(&self, now: &DateTime<Tz>) -> Option<DateTime<Tz>> {
+        let mut local = now.naive_local();
+        loop {
+            local = self.next_local(local)?;
+            if let Some(time) = now.timezone().from_local_datetime(&local).earliest() {
+                if &time > now {
+                    return Some(time);
+                }
+            }
+        }
+    }
+
+    /// 本地时间 `after` 之后的下次触发时间，精确到分钟
+    fn next_local(&self, after: NaiveDateTime) -> Option<NaiveDateTime> {
+        let start = after.with_second(0)?.with_nanosecond(0)? + Duration::minutes(1);
+        let mut date = start.date();
+        for _ in 0..SEARCH_DAYS {
+            if self.matches_date(date) {
+                let first = if date == start.date() {
+                    (start.hour(), start.minute())
+                } else {
+                    (0, 0)
+                };
+                for hour in (first.0..24).filter(|e| self.hours & (1 << e) != 0) {
+                    let from = if hour == first.0 { first.1 } else { 0 };
+                    if let Some(minute) = (from..60).find(|e| self.minutes & (1 << e) != 0) {
+                        return date.and_hms_opt(hour, minute, 0);
+                    }
+                }
+            }
+            date = date.succ_opt()?;
+        }
+        None
+    }
+
+    fn matches_date(&self, date: NaiveDate) -> bool {
+        if self.months & (1 << date.month()) == 0 {
+            return false;
+        }
+        let day = self.days & (1 << date.day()) != 0;
+        let weekday = self.weekdays & (1 << date.weekday().num_days_from_sunday()) != 0;
+        if self.day_or_weekday {
+            day || weekday
+        } else {
+            day && weekday
+        }
+    }
+}
+
+/// 解析单个字段，返回取值的位图
+fn parse_field(field: &str, min: u32, max: u32, names: &[&str]) -> Result<u64, String> {
+    let mut bits = 0u64;
+    for item in field.split(',') {
+        let (range, step) = match item.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .ok()
+                    .filter(|e| *e > 0)
+                    .ok_or_else(|| format!("无效的步长 {}", item))?,
+            ),
+            None => (item, 1),
+        };
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (
+                parse_value(start, min, names)?,
+                parse_value(end, min, names)?,
+            )
+        } else {
+            let value = parse_value(range, min, names)?;
+            // `5/10` 表示从 5 开始每 10 个取值
+            (value, if item.contains('/') { max } else { value })
+        };
+        if start < min || end > max || start > end {
+            return Err(format!("{} 超出范围 {}-{}", item, min, max));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+fn parse_value(value: &str, min: u32, names: &[&str]) -> Result<u32, String> {
+    if let Some(index) = names.iter().position(|e| e.eq_ignore_ascii_case(value)) {
+        return Ok(index as u32 + min);
+    }
+    value.parse().map_err(|_| format!("无效的取值 {}", value))
+}
+
+#[test]
+fn cron_parse_test() {
+    let cron = CronExpr::from_str("*/15 3,4 * JAN-mar 1-5").unwrap();
+    assert_eq!(cron.minutes, 1 | 1 << 15 | 1 << 30 | 1 << 45);
+    assert_eq!(cron.hours, 1 << 3 | 1 << 4);
+    assert_eq!(cron.months, 1 << 1 | 1 << 2 | 1 << 3);
+    assert_eq!(cron.weekdays, 0b111110);
+    assert!(cron.day_or_weekday.not());
+    assert_eq!(CronExpr::from_str("0 0 * * 7").unwrap().weekdays, 1);
+    assert_eq!(
+        CronExpr::from_str("5/20 * * * *").unwrap().minutes,
+        1 << 5 | 1 << 25 | 1 << 45
+    );
+    assert_eq!(
+        CronExpr::from_str("@daily")
+            .unwrap()
+            .next_local(NaiveDate::from_ymd(2022, 8, 1).and_hms(12, 0, 0)),
+        Some(NaiveDate::from_ymd(2022, 8, 2).and_hms(0, 0, 0))
+    );
+    assert_eq!(CronExpr::from_str("@daily").unwrap().to_string(), "@daily");
+    for text in [
+        "* * * *",
+        "60 * * * *",
+        "* * 0 * *",
+        "*/0 * * * *",
+        "5-1 * * * *",
+        "x * * * *",
+    ] {
+        assert!(CronExpr::from_str(text).is_err(), "{}", text);
+    }
+}
+
+#[test]
+fn cron_next_test() {
+    let time = |d: u32, h: u32, m: u32| NaiveDate::from_ymd(2022, 8, d).and_hms(h, m, 0);
+    let cron = CronExpr::from_str("30 3 * * *").unwrap();
+    assert_eq!(cron.next_local(time(1, 2, 0)), Some(time(1, 3, 30)));
+    // 恰好在触发时间时返回下一次
+    assert_eq!(cron.next_local(time(1, 3, 30)), Some(time(2, 3, 30)));
+    assert_eq!(
+        cron.next_local(time(1, 3, 29) + Duration::seconds(59)),
+        Some(time(1, 3, 30))
+    );
+    // 2022-08-01 为星期一，日与星期均被限制时满足其一即可
+    let cron = CronExpr::from_str("0 0 15 * SAT").unwrap();
+    assert_eq!(cron.next_local(time(1, 0, 0)), Some(time(6, 0, 0)));
+    assert_eq!(cron.next_local(time(13, 0, 0)), Some(time(15, 0, 0)));
+    let cron = CronExpr::from_str("0 0 29 2 *").unwrap();
+    assert_eq!(
+        cron.next_local(time(1, 0, 0)),
+        Some(NaiveDate::from_ymd(2024, 2, 29).and_hms(0, 0, 0))
+    );
+    assert_eq!(
+        CronExpr::from_str("0 0 31 2 *")
+            .unwrap()
+            .next_local(time(1, 0, 0)),
+        None
+    );
+    let now = chrono::Utc.ymd(2022, 8, 1).and_hms(23, 59, 30);
+    assert_eq!(
+        CronExpr::from_str("@hourly").unwrap().next_after(&now),
+        Some(chrono::Utc.ymd(2022, 8, 2).and_hms(0, 0, 0))
+    );
+}
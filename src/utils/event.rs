@@ -0,0 +1,195 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! 事件通知：主循环通过 [`Notifier`] 等待工作线程的状态变化，
+//! 工作线程通过 `poll` 同时等待指令 ([`EventFd`])、子进程退出 ([`PidFd`]) 与输出。
+
+use std::io;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// 跨线程的变化通知，等待方通过计数判断通知期间是否有新的变化
+#[derive(Clone)]
+pub struct Notifier {
+    inner: Arc<(Mutex<u64>, Condvar)>,
+}
+
+impl Notifier {
+    pub fn new() -> Self {
+        Notifier {
+            inner: Arc::new((Mutex::new(0), Condvar::new())),
+        }
+    }
+
+    pub fn notify(&self) {
+        let (lock, cond) = &*self.inner;
+        if let Ok(mut count) = lock.lock() {
+            *count += 1;
+            cond.notify_all();
+        }
+    }
+
+    /// 当前的通知计数，处理事件前获取，用于 [`Notifier::wait`]
+    pub fn current(&self) -> u64 {
+        self.inner.0.lock().map(|e| *e).unwrap_or(0)
+    }
+
+    /// 等待计数与 `seen` 不同或到达 `deadline`
+    pub fn wait(&self, seen: u64, deadline: Instant) {
+        let (lock, cond) = &*self.inner;
+        let mut count = match lock.lock() {
+            Ok(count) => count,
+            Err(_) => return,
+        };
+        while *count == seen {
+            let now = Instant::now();
+            if now >= deadline {
+                return;
+            }
+            count = match cond.wait_timeout(count, deadline - now) {
+                Ok((count, _)) => count,
+                Err(_) => return,
+            };
+        }
+    }
+}
+
+/// 用于唤醒 `poll` 的 eventfd
+pub struct EventFd(RawFd);
+
+impl EventFd {
+    pub fn new() -> io::Result<Self> {
+        let fd = unsafe { libc::eventfd(0, libc::EFD_NONBLOCK | libc::EFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(EventFd(fd))
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.0
+    }
+
+    pub fn notify(&self) {
+        let value: u64 = 1;
+        unsafe {
+            libc::write(self.0, &value as *const u64 as *const libc::c_void, 8);
+        }
+    }
+
+    /// 清除已有的通知
+    pub fn drain(&self) {
+        let mut value: u64 = 0;
+        unsafe {
+            libc::read(self.0, &mut value as *mut u64 as *mut libc::c_void, 8);
+        }
+    }
+}
+
+impl Drop for EventFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+/// 指向子进程的 pidfd，进程退出后变为可读，内核不支持时为 `None`
+pub struct PidFd(RawFd);
+
+impl PidFd {
+    pub fn open(pid: libc::pid_t) -> Option<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return None;
+        }
+        Some(PidFd(fd as RawFd))
+    }
+
+    pub fn fd(&self) -> RawFd {
+        self.0
+    }
+
+    /// 等待进程退出，返回进程是否已退出
+    pub fn wait(&self, timeout: Duration) -> bool {
+        let mut fds = [poll_fd(self.0)];
+        poll(&mut fds, Some(timeout));
+        fds[0].revents != 0
+    }
+}
+
+impl Drop for PidFd {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+pub fn poll_fd(fd: RawFd) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events: libc::POLLIN,
+        revents: 0,
+    }
+}
+
+/// 等待任一文件描述符就绪，`timeout` 为 `None` 时一直等待，被信号中断时直接返回
+pub fn poll(fds: &mut [libc::pollfd], timeout: Option<Duration>) {
+    let timeout = timeout
+        .map(|e| e.as_millis().min(i32::MAX as u128) as i32)
+        .unwrap_or(-1);
+    unsafe {
+        libc::poll(fds.as_mut_ptr(), fds.len() as libc::nfds_t, timeout);
+    }
+}
+
+#[test]
+fn notifier_test() {
+    let notifier = Notifier::new();
+    let seen = notifier.current();
+    let other = notifier.clone();
+    let handle = std::thread::spawn(move || other.notify());
+    notifier.wait(seen, Instant::now() + Duration::from_secs(10));
+    assert_ne!(notifier.current(), seen);
+    handle.join().unwrap();
+    // 没有新的通知时等待到超时
+    let start = Instant::now();
+    notifier.wait(notifier.current(), start + Duration::from_millis(50));
+    assert!(start.elapsed() >= Duration::from_millis(50));
+}
+
+#[test]
+fn event_fd_test() {
+    let event = EventFd::new().unwrap();
+    let mut fds = [poll_fd(event.fd())];
+    poll(&mut fds, Some(Duration::from_millis(0)));
+    assert_eq!(fds[0].revents, 0);
+    event.notify();
+    poll(&mut fds, Some(Duration::from_millis(0)));
+    assert_ne!(fds[0].revents, 0);
+    event.drain();
+    fds[0].revents = 0;
+    poll(&mut fds, Some(Duration::from_millis(0)));
+    assert_eq!(fds[0].revents, 0);
+}
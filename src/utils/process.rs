@@ -0,0 +1,240 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! 子进程管理：进程组信号、子进程收割者 (subreaper) 与逃逸后代进程的清理
+//!
+//! 程序启动时会带上环境变量 [`PROGRAM_ENV`]，后代进程即使离开进程组也会继承该变量，
+//! 下次启动前据此找到并清理残留的后代进程。
+
+use std::fs;
+use std::io;
+use std::ops::Not;
+use std::process::{Child, Command, Output, Stdio};
+use std::sync::Mutex;
+use std::thread;
+use std::time::Duration;
+
+use libc::{c_int, pid_t};
+
+use crate::log::{debug, warn};
+
+/// 标记程序后代进程的环境变量
+pub const PROGRAM_ENV: &str = "ARGS_COVERT_PROGRAM";
+
+/// 由 `Child` 管理的子进程，其退出状态由对应的 `Child` 回收
+static MANAGED: Mutex<Vec<pid_t>> = Mutex::new(Vec::new());
+
+/// 将当前进程设置为子进程收割者，脱离父进程的后代进程将被挂到当前进程下
+pub fn set_child_subreaper() -> bool {
+    unsafe { libc::prctl(libc::PR_SET_CHILD_SUBREAPER, 1, 0, 0, 0) == 0 }
+}
+
+/**
+启动子进程并登记，登记期间持有锁，避免子进程在登记前退出并被 [`reap_orphans`] 回收
+ */
+pub fn spawn_managed(command: &mut Command) -> io::Result<Child> {
+    let mut managed = MANAGED.lock().unwrap_or_else(|e| e.into_inner());
+    let child = command.spawn()?;
+    managed.push(child.id() as pid_t);
+    Ok(child)
+}
+
+/// 与 `Command::output` 相同，但子进程会被登记，其退出状态不会被 [`reap_orphans`] 回收
+pub fn output_managed(command: &mut Command) -> io::Result<Output> {
+    let child = spawn_managed(
+        command
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped()),
+    )?;
+    let pid = child.id() as pid_t;
+    let output = child.wait_with_output();
+    unregister(pid);
+    output
+}
+
+pub fn unregister(pid: pid_t) {
+    if let Ok(mut managed) = MANAGED.lock() {
+        managed.retain(|e| *e != pid);
+    }
+}
+
+/// 程序后代进程的标记值
+pub fn program_marker(name: &str) -> String {
+    format!("{}:{}", unsafe { libc::getpid() }, name)
+}
+
+/// 向以 `pid` 为组长的进程组发送信号，进程组不存在时发送给进程本身
+pub fn signal_group(pid: pid_t, signal: c_int) {
+    unsafe {
+        if libc::kill(-pid, signal) != 0 {
+            libc::kill(pid, signal);
+        }
+    }
+}
+
+/**
+结束带有 `marker` 标记的全部残留进程，并回收已挂到当前进程下的进程
+ */
+pub fn kill_marked(name: &str, marker: &str) {
+    let expected = format!("{}={}", PROGRAM_ENV, marker);
+    let own = unsafe { libc::getpid() };
+    let pids: Vec<pid_t> = list_pids()
+        .into_iter()
+        .filter(|pid| *pid != own)
+        .filter(|pid| {
+            fs::read(format!("/proc/{}/environ", pid))
+                .map(|environ| environ.split(|e| *e == 0).any(|e| e == expected.as_bytes()))
+                .unwrap_or(false)
+        })
+        .collect();
+    if pids.is_empty() {
+        return;
+    }
+    warn(format!(
+        "[{}] 发现残留的后代进程 {:?}，将强制结束.",
+        name, pids
+    ));
+    for pid in &pids {
+        unsafe {
+            libc::kill(*pid, libc::SIGKILL);
+        }
+    }
+    for pid in pids {
+        // 只有挂到当前进程下的进程才能被回收
+        for _ in 0..50 {
+            let result = unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) };
+            if result != 0 {
+                break;
+            }
+            thread::sleep(Duration::from_millis(20));
+        }
+    }
+}
+
+/**
+回收挂到当前进程下的孤儿僵尸进程
+
+程序与脚本均通过 [`spawn_managed`] 启动并登记，由各自的 `Child` 回收，不在此处理
+ */
+pub fn reap_orphans() {
+    let own = unsafe { libc::getpid() };
+    // 回收期间持有锁，避免刚启动的子进程在登记前被回收
+    let managed = match MANAGED.lock() {
+        Ok(managed) => managed,
+        Err(_) => return,
+    };
+    for pid in list_pids() {
+        if let Some((state, ppid, _)) = read_stat(pid) {
+            if state == 'Z' && ppid == own && managed.contains(&pid).not() {
+                let result = unsafe { libc::waitpid(pid, std::ptr::null_mut(), libc::WNOHANG) };
+                if result == pid {
+                    debug(format!("已回收孤儿进程 {}.", pid));
+                }
+            }
+        }
+    }
+}
+
+/// 进程的资源占用
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct ProcUsage {
+    /// 常驻内存 (字节)
+    pub rss: u64,
+    /// 用户态与内核态 CPU 时间之和 (时钟周期)
+    pub cpu_ticks: u64,
+    pub threads: u64,
+    pub fds: u64,
+}
+
+/// 从 `/proc/<pid>` 读取进程的资源占用，进程不存在时返回 `None`
+pub fn read_usage(pid: pid_t) -> Option<ProcUsage> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let page_size = unsafe { libc::sysconf(libc::_SC_PAGESIZE) }.max(1) as u64;
+    let (rss, cpu_ticks, threads) = parse_usage(&stat, page_size)?;
+    let fds = fs::read_dir(format!("/proc/{}/fd", pid))
+        .map(|e| e.count() as u64)
+        .unwrap_or(0);
+    Some(ProcUsage {
+        rss,
+        cpu_ticks,
+        threads,
+        fds,
+    })
+}
+
+/// 每秒的时钟周期数，用于换算 CPU 时间
+pub fn clock_ticks() -> u64 {
+    unsafe { libc::sysconf(libc::_SC_CLK_TCK) }.max(1) as u64
+}
+
+/// 解析 `/proc/<pid>/stat` 中的常驻内存、CPU 时间与线程数
+fn parse_usage(stat: &str, page_size: u64) -> Option<(u64, u64, u64)> {
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let field = |index: usize| fields.get(index)?.parse::<u64>().ok();
+    // 从状态字段开始计数：utime 11、stime 12、num_threads 17、rss 21
+    Some((field(21)? * page_size, field(11)? + field(12)?, field(17)?))
+}
+
+fn list_pids() -> Vec<pid_t> {
+    fs::read_dir("/proc")
+        .map(|dir| {
+            dir.filter_map(|e| e.ok())
+                .filter_map(|e| e.file_name().to_str()?.parse::<pid_t>().ok())
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+/// 读取进程状态、父进程与进程组
+fn read_stat(pid: pid_t) -> Option<(char, pid_t, pid_t)> {
+    parse_stat(&fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?)
+}
+
+fn parse_stat(stat: &str) -> Option<(char, pid_t, pid_t)> {
+    // 进程名可能包含空格与括号，从最后一个 ')' 之后开始解析
+    let fields: Vec<&str> = stat[stat.rfind(')')? + 1..].split_whitespace().collect();
+    let state = fields.first()?.chars().next()?;
+    Some((
+        state,
+        fields.get(1)?.parse().ok()?,
+        fields.get(2)?.parse().ok()?,
+    ))
+}
+
+#[test]
+fn parse_stat_test() {
+    assert_eq!(
+        parse_stat("123 (my (app) x) S 1 123 123 0 -1 4194560"),
+        Some(('S', 1, 123))
+    );
+    assert_eq!(parse_stat("123 (zombie) Z 45 67 67 0"), Some(('Z', 45, 67)));
+    assert_eq!(parse_stat("broken"), None);
+}
+
+#[test]
+fn parse_usage_test() {
+    let stat = "4007 (app (x)) S 1 4007 4007 0 -1 4194560 1000 0 0 0 250 50 0 0 20 0 12 0 \
+                123456 987654321 2048 18446744073709551615";
+    assert_eq!(parse_usage(stat, 4096), Some((2048 * 4096, 300, 12)));
+    assert_eq!(parse_usage("1 (short) S 1 1", 4096), None);
+}
@@ -0,0 +1,279 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! 资源限制：rlimit、nice、ionice 与 oom_score_adj
+//!
+//! 配置在父进程中校验，子进程在 `exec` 前 (降低权限之前) 应用。
+
+use std::ffi::CString;
+use std::io;
+use std::ops::Not;
+
+use crate::config::prop::{IoClass, ProjectInfo, RLimit, RLIM_UNLIMITED};
+use crate::lib::SoftError;
+
+const IOPRIO_WHO_PROCESS: libc::c_int = 1;
+const IOPRIO_CLASS_SHIFT: libc::c_int = 13;
+
+/// `pre_exec` 的错误只能以错误码传回父进程，失败的步骤序号 (从 1 开始) 编码在错误码的高位
+const STEP_SHIFT: i32 = 16;
+
+/// 已校验的资源限制
+#[derive(Debug, Clone)]
+pub struct ProcessLimits {
+    rlimits: Vec<(libc::__rlimit_resource_t, RLimit)>,
+    nice: Option<i32>,
+    ionice: Option<libc::c_int>,
+    oom_score_adj: Option<CString>,
+    /// 按应用顺序排列的配置项与取值，用于说明失败的步骤
+    steps: Vec<String>,
+}
+
+impl ProcessLimits {
+    /**
+    校验程序的资源限制，未配置任何限制时返回 `None`。
+
+    非 root 用户不能提高硬限制、降低 nice 值或降低 oom_score_adj，此时直接报错，避免程序启动时才失败
+     */
+    pub fn resolve(project: &ProjectInfo) -> Result<Option<ProcessLimits>, SoftError> {
+        let limits = &project.limits;
+        let root = unsafe { libc::geteuid() } == 0;
+        let mut rlimits = vec![];
+        let mut steps = vec![];
+        for (name, resource, limit) in [
+            ("nofile", libc::RLIMIT_NOFILE, limits.nofile),
+            ("nproc", libc::RLIMIT_NPROC, limits.nproc),
+            ("core", libc::RLIMIT_CORE, limits.core),
+            ("as", libc::RLIMIT_AS, limits.address_space),
+            ("memlock", libc::RLIMIT_MEMLOCK, limits.memlock),
+            ("cpu", libc::RLIMIT_CPU, limits.cpu),
+        ] {
+            let limit = match limit {
+                Some(limit) => limit,
+                None => continue,
+            };
+            let mut current = libc::rlimit {
+                rlim_cur: 0,
+                rlim_max: 0,
+            };
+            unsafe { libc::getrlimit(resource, &mut current) };
+            if root.not() && rlimit(limit).rlim_max > current.rlim_max {
+                return Err(SoftError::AppError(format!(
+                    "limits.{} 的硬限制超过当前进程的硬限制 {}，需要 root 权限",
+                    name, current.rlim_max
+                )));
+            }
+            rlimits.push((resource, limit));
+            steps.push(format!("limits.{}={}", name, limit));
+        }
+        if let Some(nice) = project.nice {
+            if (-20..=19).contains(&nice).not() {
+                return Err(SoftError::AppError(format!(
+                    "nice 的取值范围为 -20 到 19，当前为 {}",
+                    nice
+                )));
+            }
+            let current = unsafe { libc::getpriority(libc::PRIO_PROCESS as _, 0) };
+            if root.not() && nice < current {
+                return Err(SoftError::AppError(format!(
+                    "nice 低于当前进程的 {}，需要 root 权限",
+                    current
+                )));
+            }
+            steps.push(format!("nice={}", nice));
+        }
+        let ionice = match project.ionice {
+            Some(ionice) => {
+                if ionice.level > 7 {
+                    return Err(SoftError::AppError(format!(
+                        "ionice.level 的取值范围为 0 到 7，当前为 {}",
+                        ionice.level
+                    )));
+                }
+                let (class, level) = match ionice.class {
+                    IoClass::REALTIME if root.not() => {
+                        return Err(SoftError::AppError(
+                            "ionice.class 为 REALTIME 时需要 root 权限".to_string(),
+                        ))
+                    }
+                    IoClass::REALTIME => (1, ionice.level),
+                    IoClass::BEST_EFFORT => (2, ionice.level),
+                    IoClass::IDLE => (3, 0),
+                };
+                steps.push(format!(
+                    "ionice={{class: {:?}, level: {}}}",
+                    ionice.class, ionice.level
+                ));
+                Some(class << IOPRIO_CLASS_SHIFT | level as libc::c_int)
+            }
+            None => None,
+        };
+        if let Some(adj) = project.oom_score_adj {
+            if (-1000..=1000).contains(&adj).not() {
+                return Err(SoftError::AppError(format!(
+                    "oom_score_adj 的取值范围为 -1000 到 1000，当前为 {}",
+                    adj
+                )));
+            }
+            let current = std::fs::read_to_string("/proc/self/oom_score_adj")
+                .ok()
+                .and_then(|e| e.trim().parse::<i32>().ok())
+                .unwrap_or(0);
+            if root.not() && adj < current {
+                return Err(SoftError::AppError(format!(
+                    "oom_score_adj 低于当前进程的 {}，需要 root 权限",
+                    current
+                )));
+            }
+            steps.push(format!("oom_score_adj={}", adj));
+        }
+        if rlimits.is_empty()
+            && project.nice.is_none()
+            && ionice.is_none()
+            && project.oom_score_adj.is_none()
+        {
+            return Ok(None);
+        }
+        Ok(Some(ProcessLimits {
+            rlimits,
+            nice: project.nice,
+            ionice,
+            oom_score_adj: project
+                .oom_score_adj
+                .map(|e| CString::new(e.to_string()).unwrap()),
+            steps,
+        }))
+    }
+
+    /// 在 `pre_exec` 中应用资源限制，只调用异步信号安全的系统调用，失败的步骤由 [`ProcessLimits::describe`] 说明
+    pub fn apply(&self) -> io::Result<()> {
+        let mut step = 0;
+        let mut check = |failed: bool| {
+            step += 1;
+            if failed {
+                let code = io::Error::last_os_error().raw_os_error().unwrap_or(0);
+                return Err(io::Error::from_raw_os_error(code | step << STEP_SHIFT));
+            }
+            Ok(())
+        };
+        unsafe {
+            for (resource, limit) in &self.rlimits {
+                check(libc::setrlimit(*resource, &rlimit(*limit)) != 0)?;
+            }
+            if let Some(nice) = self.nice {
+                check(libc::setpriority(libc::PRIO_PROCESS as _, 0, nice) != 0)?;
+            }
+            if let Some(ionice) = self.ionice {
+                check(libc::syscall(libc::SYS_ioprio_set, IOPRIO_WHO_PROCESS, 0, ionice) != 0)?;
+            }
+            if let Some(adj) = &self.oom_score_adj {
+                let fd = libc::open(
+                    c"/proc/self/oom_score_adj".as_ptr(),
+                    libc::O_WRONLY | libc::O_CLOEXEC,
+                );
+                let written = if fd < 0 {
+                    -1
+                } else {
+                    let bytes = adj.as_bytes();
+                    let written =
+                        libc::write(fd, bytes.as_ptr() as *const libc::c_void, bytes.len());
+                    libc::close(fd);
+                    written
+                };
+                check(written < 0)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// 说明 [`ProcessLimits::apply`] 失败的配置项，不是应用资源限制产生的错误时返回 `None`
+    pub fn describe(&self, error: &io::Error) -> Option<String> {
+        let code = error.raw_os_error()?;
+        let step = self
+            .steps
+            .get(((code >> STEP_SHIFT) as usize).checked_sub(1)?)?;
+        let error = io::Error::from_raw_os_error(code & ((1 << STEP_SHIFT) - 1));
+        Some(format!("无法应用资源限制 {}: {}", step, error))
+    }
+}
+
+fn rlimit(limit: RLimit) -> libc::rlimit {
+    let value = |value: u64| match value {
+        RLIM_UNLIMITED => libc::RLIM_INFINITY,
+        value => value as libc::rlim_t,
+    };
+    libc::rlimit {
+        rlim_cur: value(limit.soft),
+        rlim_max: value(limit.hard),
+    }
+}
+
+#[test]
+fn process_limits_test() {
+    let project: ProjectInfo = serde_yaml::from_str("{name: app, binary: app.sh}").unwrap();
+    assert!(ProcessLimits::resolve(&project).unwrap().is_none());
+    let project: ProjectInfo = serde_yaml::from_str(
+        "{name: app, binary: app.sh, limits: {core: 0}, nice: 5, ionice: {class: IDLE}}",
+    )
+    .unwrap();
+    let limits = ProcessLimits::resolve(&project).unwrap().unwrap();
+    assert_eq!(limits.rlimits.len(), 1);
+    assert_eq!(limits.ionice, Some(3 << IOPRIO_CLASS_SHIFT));
+    let project: ProjectInfo =
+        serde_yaml::from_str("{name: app, binary: app.sh, nice: 30}").unwrap();
+    assert!(ProcessLimits::resolve(&project).is_err());
+    let project: ProjectInfo =
+        serde_yaml::from_str("{name: app, binary: app.sh, oom_score_adj: 2000}").unwrap();
+    assert!(ProcessLimits::resolve(&project).is_err());
+}
+
+#[test]
+fn process_limits_error_test() {
+    use std::os::unix::process::CommandExt;
+    use std::process::Command;
+
+    let project: ProjectInfo =
+        serde_yaml::from_str("{name: app, binary: app.sh, limits: {core: 0}}").unwrap();
+    let mut limits = ProcessLimits::resolve(&project).unwrap().unwrap();
+    // 软限制大于硬限制，setrlimit 返回 EINVAL
+    limits
+        .rlimits
+        .push((libc::RLIMIT_NOFILE, RLimit { soft: 2, hard: 1 }));
+    limits.steps.push("limits.nofile=2:1".to_string());
+    let error = unsafe {
+        let limits = limits.clone();
+        Command::new("true")
+            .pre_exec(move || limits.apply())
+            .spawn()
+            .unwrap_err()
+    };
+    assert_eq!(
+        limits.describe(&error).unwrap(),
+        format!(
+            "无法应用资源限制 limits.nofile=2:1: {}",
+            io::Error::from_raw_os_error(libc::EINVAL)
+        )
+    );
+    assert!(limits
+        .describe(&io::Error::from_raw_os_error(libc::ENOENT))
+        .is_none());
+}
@@ -22,52 +22,88 @@
 
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::mpsc::{channel, Receiver, Sender};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 use std::thread;
 
-use libc::c_int;
+use libc::{c_int, pid_t};
 use signal_hook::flag;
 use signal_hook::iterator::exfiltrator::WithOrigin;
 use signal_hook::iterator::{Handle, SignalsInfo};
 
+use crate::utils::event::Notifier;
+use crate::utils::process::signal_group;
+
+/// 停止阶段直接转发的信号量与目标进程组
+type ForwardTargets = Arc<Mutex<Option<(Vec<c_int>, Vec<pid_t>)>>>;
+
 pub struct UnixSignalHook {
     rx: Receiver<c_int>,
     handle: Handle,
     signal_accept: Arc<AtomicBool>,
+    /// 停止阶段由信号线程直接转发，为 `None` 时信号交给主循环处理
+    forward: ForwardTargets,
 }
 
 impl UnixSignalHook {
     pub fn signals(&self) -> Vec<c_int> {
         let mut watch_id: Vec<c_int> = vec![];
-        if let Ok(item) = self.rx.try_recv() {
+        while let Ok(item) = self.rx.try_recv() {
             watch_id.push(item);
-        };
+        }
         watch_id
     }
 }
 
 impl UnixSignalHook {
+    /**
+    进入停止阶段：再次收到 `stop_signals` 时执行信号的默认动作，
+    `signals` 中的信号直接转发给 `pids` 的进程组，直到 [`UnixSignalHook::close`]
+     */
+    pub fn shutdown(&self, signals: &[c_int], pids: Vec<pid_t>) {
+        if let Ok(mut forward) = self.forward.lock() {
+            *forward = Some((signals.to_vec(), pids));
+        }
+        self.signal_accept.swap(true, Ordering::Release);
+    }
+
+    /// 程序全部停止后不再接收信号
     pub fn close(&self) {
         self.handle.close();
         self.signal_accept.swap(true, Ordering::Release);
     }
-    pub fn new(signals: Vec<c_int>) -> Self {
+
+    /// `stop_signals` 为停止信号，停止阶段再次收到时执行默认动作以强制退出
+    pub fn new(signals: Vec<c_int>, stop_signals: &[c_int], notifier: &Notifier) -> Self {
         let (tx, rx): (Sender<c_int>, Receiver<c_int>) = channel();
         let signal_accept = Arc::new(AtomicBool::new(false));
-        for signal in &signals {
+        for signal in signals.iter().filter(|e| stop_signals.contains(e)) {
             flag::register_conditional_default(*signal, Arc::clone(&signal_accept)).unwrap();
         }
+        let forward: ForwardTargets = Arc::new(Mutex::new(None));
         let mut info = SignalsInfo::<WithOrigin>::new(Vec::clone(&signals)).unwrap();
         let handle = info.handle();
+        let notifier = notifier.clone();
+        let targets = Arc::clone(&forward);
         thread::spawn(move || {
             for item in &mut info {
+                if let Some((signals, pids)) = targets.lock().unwrap().as_ref() {
+                    // 主循环已结束，由信号线程转发
+                    if signals.contains(&item.signal) {
+                        for pid in pids {
+                            signal_group(*pid, item.signal);
+                        }
+                    }
+                    continue;
+                }
                 tx.send(item.signal).unwrap();
+                notifier.notify();
             }
         });
         UnixSignalHook {
             rx,
             handle,
             signal_accept,
+            forward,
         }
     }
 }
@@ -61,6 +61,45 @@ pub fn replace_all_str(src: &mut String, data: &Vec<(String, String)>) {
     }
 }
 
+/**
+简单的通配符匹配，`*` 匹配任意个字符，`?` 匹配单个字符
+ */
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let text: Vec<char> = text.chars().collect();
+    let (mut p, mut t) = (0, 0);
+    // 最近一次 `*` 的位置以及它当时对应的文本位置，用于回溯
+    let mut star: Option<(usize, usize)> = None;
+    while t < text.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == text[t]) {
+            p += 1;
+            t += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, t));
+            p += 1;
+        } else if let Some((star_p, star_t)) = star {
+            p = star_p + 1;
+            t = star_t + 1;
+            star = Some((star_p, star_t + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|e| *e == '*')
+}
+
+#[test]
+fn glob_match_test() {
+    assert!(glob_match("*", ""));
+    assert!(glob_match("LC_*", "LC_ALL"));
+    assert!(glob_match("*_TOKEN", "GITHUB_TOKEN"));
+    assert!(glob_match("A?C*", "ABCDEF"));
+    assert!(glob_match("*a*b", "xaxxab"));
+    assert!(glob_match("PATH", "PATH"));
+    assert!(glob_match("PATH", "PATHS").not());
+    assert!(glob_match("LC_*", "LANG").not());
+}
+
 #[test]
 fn get_value_from_exp_test() {
     let map: HashMap<String, String> = vec![
@@ -85,6 +124,32 @@ fn get_value_from_exp_test() {
     );
 }
 
+#[test]
+fn find_variables_test() {
+    assert_eq!(
+        find_variables("{{a}}:{{b ? c ? }}/x"),
+        vec![
+            vec!["a".to_string()],
+            vec!["b".to_string(), "c".to_string(), "".to_string()]
+        ]
+    );
+    assert!(find_variables("no variables").is_empty());
+}
+
+/// 查找表达式中引用的变量，每个变量返回其全部候选 key，空字符串表示默认为空
+pub fn find_variables(exp: &str) -> Vec<Vec<String>> {
+    let variable_regex = Regex::new("\\{\\{\\w.*?}}").unwrap();
+    variable_regex
+        .find_iter(exp)
+        .map(|e| {
+            exp[e.start() + 2..e.end() - 2]
+                .split('?')
+                .map(|item| item.trim().to_string())
+                .collect()
+        })
+        .collect()
+}
+
 /// 替换内部变量，如果失败则返回空
 pub fn get_value_from_exp(exp: &str, vars: &HashMap<String, String>) -> Option<String> {
     let variable_regex = Regex::new("\\{\\{\\w.*?}}").unwrap();
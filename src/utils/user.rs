@@ -0,0 +1,236 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+//! 运行用户：解析用户与用户组，并在子进程 `exec` 前降低权限
+//!
+//! 用户与用户组在父进程中解析，`fork` 之后只调用异步信号安全的系统调用。
+
+use std::ffi::{CStr, CString};
+use std::io;
+use std::ops::Not;
+use std::os::unix::ffi::OsStrExt;
+use std::path::Path;
+use std::ptr;
+
+use libc::{gid_t, uid_t};
+
+use crate::lib::SoftError;
+
+/// 子进程的运行用户、主用户组与附加用户组
+#[derive(Debug, Clone, PartialEq)]
+pub struct Credential {
+    pub uid: uid_t,
+    pub gid: gid_t,
+    pub groups: Vec<gid_t>,
+    pub name: String,
+    pub home: String,
+}
+
+impl Credential {
+    /**
+    解析运行用户，`user` 与 `group` 可以是名称或数字，均为空时返回 `None`。
+
+    未配置 `group` 时使用用户的主用户组，未配置 `supplementary_groups` 时使用用户所属的全部用户组
+     */
+    pub fn resolve(
+        user: &str,
+        group: &str,
+        supplementary_groups: &[String],
+    ) -> Result<Option<Credential>, SoftError> {
+        if user.is_empty() && group.is_empty() && supplementary_groups.is_empty() {
+            return Ok(None);
+        }
+        let current = unsafe { libc::geteuid() };
+        let passwd = if user.is_empty() {
+            lookup_user(&current.to_string())?
+        } else {
+            lookup_user(user)?
+        };
+        let gid = if group.is_empty() {
+            passwd.gid
+        } else {
+            lookup_group(group)?
+        };
+        let mut groups = vec![gid];
+        if supplementary_groups.is_empty() && user.is_empty().not() {
+            groups.extend(user_groups(&passwd.name, passwd.gid));
+        }
+        for group in supplementary_groups {
+            groups.push(lookup_group(group)?);
+        }
+        groups.sort_unstable();
+        groups.dedup();
+        let credential = Credential {
+            uid: passwd.uid,
+            gid,
+            groups,
+            name: passwd.name,
+            home: passwd.home,
+        };
+        if current != 0 && (credential.uid != current || gid != unsafe { libc::getegid() }) {
+            return Err(SoftError::AppError(format!(
+                "当前用户没有权限以用户 {} 运行程序",
+                user
+            )));
+        }
+        Ok(Some(credential))
+    }
+
+    /// 在 `pre_exec` 中切换到运行用户，只调用异步信号安全的系统调用
+    pub fn drop_privileges(&self) -> io::Result<()> {
+        unsafe {
+            if libc::geteuid() == 0
+                && libc::setgroups(self.groups.len() as _, self.groups.as_ptr()) != 0
+            {
+                return Err(io::Error::last_os_error());
+            }
+            if libc::setgid(self.gid) != 0 || libc::setuid(self.uid) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+        }
+        Ok(())
+    }
+
+    /// 将文件的所有者调整为运行用户
+    pub fn chown(&self, path: &Path) -> io::Result<()> {
+        let path = CString::new(path.as_os_str().as_bytes())?;
+        if unsafe { libc::chown(path.as_ptr(), self.uid, self.gid) } != 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(())
+    }
+}
+
+struct Passwd {
+    uid: uid_t,
+    gid: gid_t,
+    name: String,
+    home: String,
+}
+
+fn lookup_user(user: &str) -> Result<Passwd, SoftError> {
+    let mut passwd: libc::passwd = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::passwd = ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let code = match user.parse::<uid_t>() {
+        Ok(uid) => unsafe {
+            libc::getpwuid_r(
+                uid,
+                &mut passwd,
+                buffer.as_mut_ptr(),
+                buffer.len(),
+                &mut result,
+            )
+        },
+        Err(_) => {
+            let name = CString::new(user)
+                .map_err(|_| SoftError::AppError(format!("无效的用户名 {}", user)))?;
+            unsafe {
+                libc::getpwnam_r(
+                    name.as_ptr(),
+                    &mut passwd,
+                    buffer.as_mut_ptr(),
+                    buffer.len(),
+                    &mut result,
+                )
+            }
+        }
+    };
+    if code != 0 || result.is_null() {
+        // 数字形式的用户可以不存在于 passwd 中
+        return match user.parse::<uid_t>() {
+            Ok(uid) => Ok(Passwd {
+                uid,
+                gid: uid,
+                name: user.to_string(),
+                home: "/".to_string(),
+            }),
+            Err(_) => Err(SoftError::AppError(format!("用户 {} 不存在", user))),
+        };
+    }
+    unsafe {
+        Ok(Passwd {
+            uid: passwd.pw_uid,
+            gid: passwd.pw_gid,
+            name: CStr::from_ptr(passwd.pw_name).to_string_lossy().to_string(),
+            home: CStr::from_ptr(passwd.pw_dir).to_string_lossy().to_string(),
+        })
+    }
+}
+
+fn lookup_group(group: &str) -> Result<gid_t, SoftError> {
+    if let Ok(gid) = group.parse::<gid_t>() {
+        return Ok(gid);
+    }
+    let name =
+        CString::new(group).map_err(|_| SoftError::AppError(format!("无效的用户组 {}", group)))?;
+    let mut entry: libc::group = unsafe { std::mem::zeroed() };
+    let mut result: *mut libc::group = ptr::null_mut();
+    let mut buffer = vec![0 as libc::c_char; 16384];
+    let code = unsafe {
+        libc::getgrnam_r(
+            name.as_ptr(),
+            &mut entry,
+            buffer.as_mut_ptr(),
+            buffer.len(),
+            &mut result,
+        )
+    };
+    if code != 0 || result.is_null() {
+        return Err(SoftError::AppError(format!("用户组 {} 不存在", group)));
+    }
+    Ok(entry.gr_gid)
+}
+
+/// 用户所属的全部用户组
+fn user_groups(name: &str, gid: gid_t) -> Vec<gid_t> {
+    let name = match CString::new(name) {
+        Ok(name) => name,
+        Err(_) => return vec![],
+    };
+    let mut count: libc::c_int = 64;
+    loop {
+        let mut groups = vec![0 as gid_t; count as usize];
+        let code =
+            unsafe { libc::getgrouplist(name.as_ptr(), gid, groups.as_mut_ptr(), &mut count) };
+        if code >= 0 {
+            groups.truncate(count as usize);
+            return groups;
+        }
+        if count as usize <= groups.len() {
+            count = groups.len() as libc::c_int * 2;
+        }
+    }
+}
+
+#[test]
+fn credential_resolve_test() {
+    assert_eq!(Credential::resolve("", "", &[]).unwrap(), None);
+    let current = unsafe { libc::geteuid() };
+    let credential = Credential::resolve(&current.to_string(), "", &[])
+        .unwrap()
+        .unwrap();
+    assert_eq!(credential.uid, current);
+    assert!(credential.groups.contains(&credential.gid));
+    assert!(Credential::resolve("no-such-user-args-tools", "", &[]).is_err());
+    assert!(Credential::resolve("", "no-such-group-args-tools", &[]).is_err());
+}
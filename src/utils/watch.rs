@@ -0,0 +1,245 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+//! 文件监听：通过 inotify 监听文件所在的目录，编辑器以重命名方式保存文件、文件被替换时同样能够发现变化
+
+use std::collections::HashMap;
+use std::ffi::CString;
+use std::io;
+use std::mem::size_of;
+use std::ops::Not;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::io::RawFd;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use crate::log::{debug, warn};
+use crate::utils::event::{poll, poll_fd, EventFd, Notifier};
+use crate::utils::string::glob_match;
+
+const WATCH_MASK: u32 = libc::IN_CLOSE_WRITE
+    | libc::IN_MOVED_TO
+    | libc::IN_MOVED_FROM
+    | libc::IN_CREATE
+    | libc::IN_DELETE
+    | libc::IN_ATTRIB;
+
+/// 监听一组文件的变化，发现变化时通过 [`Notifier`] 唤醒主循环
+pub struct FileWatcher {
+    changed: Arc<Mutex<Vec<PathBuf>>>,
+    close: Arc<EventFd>,
+}
+
+struct Inotify(RawFd);
+
+impl Inotify {
+    fn fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+impl Drop for Inotify {
+    fn drop(&mut self) {
+        unsafe {
+            libc::close(self.0);
+        }
+    }
+}
+
+impl FileWatcher {
+    /**
+    监听 `paths` 的变化：文件监听其本身，目录监听其中的文件 (不包含子目录)，文件名可以使用通配符，如 `lib` 目录下的 `*.jar`；
+    文件名匹配 `exclude` 中任一通配符的文件被忽略
+     */
+    pub fn new(paths: &[PathBuf], exclude: &[String], notifier: &Notifier) -> io::Result<Self> {
+        let fd = unsafe { libc::inotify_init1(libc::IN_NONBLOCK | libc::IN_CLOEXEC) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        let inotify = Inotify(fd);
+        // 监听的目录及其中需要关注的文件名通配符
+        let mut dirs: HashMap<i32, (PathBuf, Vec<String>)> = HashMap::new();
+        for path in paths {
+            let (dir, pattern) = if path.is_dir() {
+                (path.as_path(), "*".to_string())
+            } else {
+                match (path.parent(), path.file_name()) {
+                    (Some(dir), Some(name)) => (dir, name.to_string_lossy().to_string()),
+                    _ => continue,
+                }
+            };
+            if let Some((_, patterns)) = dirs.values_mut().find(|(e, _)| e == dir) {
+                patterns.push(pattern);
+                continue;
+            }
+            let path = CString::new(dir.as_os_str().as_bytes())
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, e))?;
+            let wd = unsafe { libc::inotify_add_watch(inotify.fd(), path.as_ptr(), WATCH_MASK) };
+            if wd < 0 {
+                warn(format!(
+                    "无法监听目录 {:?}: {}",
+                    dir,
+                    io::Error::last_os_error()
+                ));
+                continue;
+            }
+            dirs.insert(wd, (dir.to_path_buf(), vec![pattern]));
+        }
+        let changed = Arc::new(Mutex::new(vec![]));
+        let close = Arc::new(EventFd::new()?);
+        let exclude = exclude.to_vec();
+        let thread_changed = Arc::clone(&changed);
+        let thread_close = Arc::clone(&close);
+        let notifier = notifier.clone();
+        thread::spawn(move || loop {
+            let mut fds = [poll_fd(inotify.fd()), poll_fd(thread_close.fd())];
+            poll(&mut fds, None);
+            if fds[1].revents != 0 {
+                break;
+            }
+            if fds[0].revents == 0 {
+                continue;
+            }
+            let mut found = vec![];
+            for (wd, mask, name) in read_events(inotify.fd()) {
+                if mask & libc::IN_Q_OVERFLOW != 0 {
+                    // 事件队列溢出，无法确定变化的文件
+                    found = dirs.values().map(|(e, _)| e.to_path_buf()).collect();
+                    break;
+                }
+                if mask & libc::IN_IGNORED != 0 {
+                    if let Some((dir, _)) = dirs.remove(&wd) {
+                        warn(format!("目录 {:?} 已被删除，停止监听", dir));
+                    }
+                    continue;
+                }
+                let (dir, patterns) = match dirs.get(&wd) {
+                    Some(dir) => dir,
+                    None => continue,
+                };
+                let name = String::from_utf8_lossy(&name);
+                if patterns.iter().any(|e| glob_match(e, &name)).not()
+                    || exclude.iter().any(|e| glob_match(e, &name))
+                {
+                    continue;
+                }
+                let path = dir.join(name.as_ref());
+                if found.contains(&path).not() {
+                    found.push(path);
+                }
+            }
+            if found.is_empty() {
+                continue;
+            }
+            debug(format!("文件发生变化: {:?}", found));
+            if let Ok(mut changed) = thread_changed.lock() {
+                for path in found {
+                    if changed.contains(&path).not() {
+                        changed.push(path);
+                    }
+                }
+            }
+            notifier.notify();
+        });
+        Ok(FileWatcher { changed, close })
+    }
+
+    /// 取出自上次调用以来发生变化的文件
+    pub fn changed(&self) -> Vec<PathBuf> {
+        self.changed
+            .lock()
+            .map(|mut e| e.drain(..).collect())
+            .unwrap_or_default()
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.close.notify();
+    }
+}
+
+/// 读取全部待处理的事件
+fn read_events(fd: RawFd) -> Vec<(i32, u32, Vec<u8>)> {
+    let mut events = vec![];
+    // inotify_event 需要按 4 字节对齐
+    let mut buf = [0u64; 512];
+    loop {
+        let size = unsafe {
+            libc::read(
+                fd,
+                buf.as_mut_ptr() as *mut libc::c_void,
+                size_of::<[u64; 512]>(),
+            )
+        };
+        if size <= 0 {
+            break;
+        }
+        let bytes = unsafe { std::slice::from_raw_parts(buf.as_ptr() as *const u8, size as usize) };
+        events.extend(parse_events(bytes));
+    }
+    events
+}
+
+/// 解析 inotify 事件，返回监听描述符、事件类型与文件名
+fn parse_events(buf: &[u8]) -> Vec<(i32, u32, Vec<u8>)> {
+    let header = size_of::<libc::inotify_event>();
+    let mut events = vec![];
+    let mut offset = 0;
+    while offset + header <= buf.len() {
+        let event: libc::inotify_event =
+            unsafe { std::ptr::read_unaligned(buf[offset..].as_ptr() as *const _) };
+        let start = offset + header;
+        let end = (start + event.len as usize).min(buf.len());
+        // 文件名以 NUL 结尾并可能带有对齐用的填充
+        let name: Vec<u8> = buf[start..end]
+            .iter()
+            .cloned()
+            .take_while(|e| *e != 0)
+            .collect();
+        events.push((event.wd, event.mask, name));
+        offset = end;
+    }
+    events
+}
+
+#[test]
+fn parse_events_test() {
+    let mut buf = vec![];
+    for (wd, mask, name) in [
+        (1i32, libc::IN_CLOSE_WRITE, &b"app.yaml\0\0\0\0\0\0\0\0"[..]),
+        (2, libc::IN_IGNORED, &b""[..]),
+    ] {
+        buf.extend_from_slice(&wd.to_ne_bytes());
+        buf.extend_from_slice(&mask.to_ne_bytes());
+        buf.extend_from_slice(&0u32.to_ne_bytes());
+        buf.extend_from_slice(&(name.len() as u32).to_ne_bytes());
+        buf.extend_from_slice(name);
+    }
+    assert_eq!(
+        parse_events(&buf),
+        vec![
+            (1, libc::IN_CLOSE_WRITE, b"app.yaml".to_vec()),
+            (2, libc::IN_IGNORED, vec![]),
+        ]
+    );
+}
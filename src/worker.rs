@@ -21,4 +21,7 @@
  */
 
 pub mod binary_worker;
+pub mod output;
 pub mod script_worker;
+pub mod supervisor;
+pub mod watchdog;
@@ -20,27 +20,60 @@
  * SOFTWARE.
  */
 
-use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::ops::Not;
 use std::os::unix::prelude::CommandExt;
-use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::sync::{mpsc, Arc, Mutex};
-use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use std::os::unix::process::ExitStatusExt;
+use std::path::Path;
+use std::process::{Child, Command, ExitStatus};
+use std::sync::mpsc::{Receiver, SendError, SyncSender};
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use std::{env, fs, thread};
 
-use libc::SIGTERM;
-use nonblock::NonBlockingReader;
+use libc::{SIGKILL, SIGTERM};
 
-use crate::config::prop::SoftSignals;
-use crate::log::{debug, debug_str, error, error_str, info, trace_str, warn};
-use crate::worker::binary_worker::CallbackAction::{CREATED, DESTROYED, EXITED, STARTED};
+use crate::config::prop::{OutputConfig, Signal, StopStep};
+use crate::log::{debug, debug_str, error, info, trace_str, warn};
+use crate::utils::command::ExecPolicy;
+use crate::utils::event::{poll, poll_fd, EventFd, Notifier, PidFd};
+use crate::utils::process::{
+    kill_marked, output_managed, program_marker, signal_group, spawn_managed, unregister,
+    PROGRAM_ENV,
+};
+use crate::utils::signal::signal_name;
+use crate::worker::binary_worker::CallbackAction::{
+    CREATED, DESTROYED, EXITED, OOM_KILLED, STARTED,
+};
 use crate::worker::binary_worker::ChildThreadAction::{EXIT, KILL, RESTART, START};
+use crate::worker::output::StreamKind::{STDERR, STDOUT};
+use crate::worker::output::{stdio, OutputStream};
+use crate::worker::supervisor::EXIT_BEFORE_SCRIPT;
 
 pub struct StableWorker {
     pub master_rx: SyncSender<ChildThreadAction>,
-    pub status: Arc<Mutex<CallbackAction>>,
+    shared: Arc<WorkerShared>,
+}
+
+/// 主线程与工作线程共享的状态与通知
+struct WorkerShared {
+    status: Mutex<WorkerState>,
+    changed: Condvar,
+    /// 唤醒工作线程处理指令
+    wake: EventFd,
+    /// 状态变化时通知主循环
+    notifier: Notifier,
+}
+
+/// 工作线程状态，`launch` 为已处理的启动次数，用于区分状态属于哪一次启动
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct WorkerState {
+    pub launch: u64,
+    pub action: CallbackAction,
+    /// 当前运行的子进程
+    pub pid: Option<i32>,
+    /// 最近一次退出的退出码，被信号结束时为 128 + 信号量
+    pub exit_code: Option<i32>,
 }
 
 #[derive(PartialEq)]
@@ -52,75 +85,167 @@ pub enum ChildThreadAction {
     RESTART,
 }
 
-#[derive(PartialEq, Debug)]
+#[allow(non_camel_case_types)]
+#[derive(PartialEq, Debug, Copy, Clone)]
 pub enum CallbackAction {
     CREATED,
     STARTED,
-    EXITED(i32),
+    EXITED(ExitInfo),
+    /// 程序因内存超出 cgroup 上限被 OOM Killer 结束
+    OOM_KILLED,
     DESTROYED,
 }
 
+/// 程序的退出状态，正常退出时 `signal` 为 `None`，被信号结束时 `code` 为 `None`
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub struct ExitInfo {
+    pub code: Option<i32>,
+    pub signal: Option<i32>,
+}
+
+impl ExitInfo {
+    pub fn code(code: i32) -> Self {
+        ExitInfo {
+            code: Some(code),
+            signal: None,
+        }
+    }
+
+    pub fn signal(signal: i32) -> Self {
+        ExitInfo {
+            code: None,
+            signal: Some(signal),
+        }
+    }
+
+    /// 以数字表示的退出码，被信号结束时为 128 + 信号量
+    pub fn exit_code(&self) -> i32 {
+        self.code
+            .or_else(|| self.signal.map(|e| 128 + e))
+            .unwrap_or(1)
+    }
+
+    /// 传递给钩子脚本的环境变量 `EXIT_CODE` 与 `EXIT_SIGNAL`，不存在的项为空
+    pub fn envs(&self) -> [(&'static str, String); 2] {
+        [
+            (
+                "EXIT_CODE",
+                self.code.map(|e| e.to_string()).unwrap_or_default(),
+            ),
+            (
+                "EXIT_SIGNAL",
+                self.signal.map(signal_name).unwrap_or_default(),
+            ),
+        ]
+    }
+}
+
+impl From<ExitStatus> for ExitInfo {
+    fn from(status: ExitStatus) -> Self {
+        ExitInfo {
+            code: status.code(),
+            signal: status.signal(),
+        }
+    }
+}
+
+impl Display for ExitInfo {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match (self.code, self.signal) {
+            (Some(code), _) => write!(f, "退出码 {}", code),
+            (None, Some(signal)) => write!(f, "信号 {}", signal_name(signal)),
+            (None, None) => write!(f, "未知状态"),
+        }
+    }
+}
+
 impl StableWorker {
     pub fn wait_exited(&self) {
-        loop {
-            if let Ok(data) = self.status.try_lock() {
-                if DESTROYED == *data {
-                    break;
-                }
-            }
-            thread::sleep(Duration::from_millis(100));
+        let mut state = self.shared.status.lock().unwrap();
+        while state.action != DESTROYED {
+            state = self.shared.changed.wait(state).unwrap();
         }
     }
     #[allow(dead_code)]
     pub fn stop(&self) {
-        self.master_rx.send(KILL(SIGTERM)).unwrap();
+        self.send(KILL(SIGTERM)).unwrap();
     }
     pub fn restart(&self) {
-        self.master_rx.send(RESTART).unwrap();
+        self.send(RESTART).unwrap();
     }
     pub fn exit(&self) {
-        self.master_rx.send(EXIT).unwrap();
+        // 工作线程可能已经退出
+        self.send(EXIT).ok();
     }
     pub fn start(&self) {
-        self.master_rx.send(START).unwrap();
+        self.send(START).unwrap();
+    }
+    pub fn state(&self) -> WorkerState {
+        *self.shared.status.lock().unwrap()
+    }
+    fn send(&self, action: ChildThreadAction) -> Result<(), SendError<ChildThreadAction>> {
+        self.master_rx.send(action)?;
+        self.shared.wake.notify();
+        Ok(())
     }
 
+    #[allow(clippy::too_many_arguments)]
     fn thread_fun(
         nio_rx: Receiver<ChildThreadAction>,
+        name: String,
         binary: String,
         args: Vec<String>,
-        envs: HashMap<String, String>,
-        callback_action: Arc<Mutex<CallbackAction>>,
-        signals: SoftSignals,
+        policy: ExecPolicy,
+        shared: Arc<WorkerShared>,
+        stop_steps: Vec<StopStep>,
+        output: OutputConfig,
         hooks: HookScripts,
     ) {
+        let update = |change: &dyn Fn(&mut WorkerState)| {
+            if let Ok(mut lock) = shared.status.lock() {
+                change(&mut lock);
+            }
+            shared.changed.notify_all();
+            shared.notifier.notify();
+        };
+        let set_action = |action: CallbackAction| update(&|state| state.action = action);
         let system_time = SystemTime::now();
         let duration = system_time.duration_since(UNIX_EPOCH).unwrap();
         let before_script_path = Path::new(env::temp_dir().as_path())
-            .join(format!("args-before-script-{:?}.sh", duration));
+            .join(format!("args-before-script-{}-{:?}.sh", name, duration));
         let after_script_path = Path::new(env::temp_dir().as_path())
-            .join(format!("args-after-script-{:?}.sh", duration));
+            .join(format!("args-after-script-{}-{:?}.sh", name, duration));
         debug(format!(
             "脚本钩子文件位于 {:?} 和 {:?}.",
             before_script_path, after_script_path
         ));
         if let Some(data) = &hooks.before_script {
             fs::write(&before_script_path, data).expect("前置脚本钩子无法写入！");
+            hooks.policy.own(&before_script_path);
         }
         if let Some(data) = &hooks.after_script {
             fs::write(&after_script_path, data).expect("后置脚本钩子无法写入！");
+            hooks.policy.own(&after_script_path);
         }
         let mut restart = false;
+        let marker = program_marker(&name);
         debug_str("子进程开始启动.");
+        // 启动前脚本失败时视为程序以 EXIT_BEFORE_SCRIPT 退出
+        let before_failed = || {
+            update(&|state| {
+                state.exit_code = Some(EXIT_BEFORE_SCRIPT);
+                state.action = EXITED(ExitInfo::code(EXIT_BEFORE_SCRIPT));
+            })
+        };
         let before_hook = || -> bool {
             if let Some(_) = &hooks.before_script {
                 debug_str("发现启动前钩子，开始执行脚本钩子.");
                 let before_path = before_script_path.to_str().unwrap().to_string();
-                if let Ok(data) = Command::new(&hooks.script_worker)
-                    .envs(&envs)
-                    .arg(&before_path)
-                    .output()
-                {
+                if let Ok(data) = output_managed(
+                    hooks
+                        .policy
+                        .apply(Command::new(&hooks.script_worker).arg(&before_path)),
+                ) {
                     info(format!(
                         "前置钩子标准输出 - {} => \n {}",
                         &before_path,
@@ -133,37 +258,30 @@ impl StableWorker {
                     }
 
                     if data.status.code().unwrap_or(-1) != 0 {
-                        {
-                            if let Ok(mut lock) = callback_action.lock() {
-                                *lock = EXITED(1);
-                            }
-                        }
-                        error_str("前置钩子执行失败，返回码不为 0");
+                        before_failed();
+                        error(format!("[{}] 前置钩子执行失败，返回码不为 0", name));
                         return false;
                     } else {
                         debug_str("前置钩子执行完成。");
                     }
                 } else {
-                    {
-                        if let Ok(mut lock) = callback_action.lock() {
-                            *lock = EXITED(1);
-                        }
-                    }
-                    error(format!("前置钩子执行失败,内部流程出现问题"));
+                    before_failed();
+                    error(format!("[{}] 前置钩子执行失败,内部流程出现问题", name));
                     return false;
                 }
             }
             true
         };
-        let destroy_hook = || -> () {
+        let destroy_hook = |exit: Option<ExitInfo>| {
             if let Some(_) = &hooks.after_script {
                 debug_str("发现销毁钩子，开始执行脚本.");
                 let after_path = after_script_path.to_str().unwrap().to_string();
-                if let Ok(data) = Command::new(&hooks.script_worker)
-                    .envs(&envs)
-                    .arg(&after_path)
-                    .output()
-                {
+                let mut command = Command::new(&hooks.script_worker);
+                hooks.policy.apply(&mut command).arg(&after_path);
+                if let Some(exit) = exit {
+                    command.envs(exit.envs());
+                }
+                if let Ok(data) = output_managed(&mut command) {
                     for x in Some(String::from_utf8_lossy(&data.stdout).to_string())
                         .filter(|e| e.trim().is_empty().not())
                     {
@@ -178,21 +296,12 @@ impl StableWorker {
             }
         };
         'e: loop {
-            {
-                if let Ok(mut lock) = callback_action.lock() {
-                    if let EXITED(_) = *lock {
-                    } else {
-                        debug_str("确认程序退出");
-                        *lock = EXITED(0);
-                    }
-                }
-            };
-            if before_hook().not() {
-                continue;
-            }
             if !restart {
                 debug_str("等待启动命令唤醒");
-                let action = nio_rx.recv().unwrap();
+                let action = match nio_rx.recv() {
+                    Ok(action) => action,
+                    Err(_) => break,
+                };
                 match action {
                     // 等待启动指令
                     START => {}
@@ -205,20 +314,28 @@ impl StableWorker {
                 debug_str("当前为重启模式，项目重启中.");
             }
             restart = false;
-            {
-                if let Ok(mut lock) = callback_action.lock() {
-                    *lock = CREATED;
-                }
-            };
+            // 清理上次运行残留的后代进程
+            kill_marked(&name, &marker);
+            if let Some(cgroup) = &policy.cgroup {
+                cgroup.kill(&name);
+            }
+            let oom_kills = policy.cgroup.as_ref().map(|e| e.oom_kills()).unwrap_or(0);
+            update(&|state| {
+                state.launch += 1;
+                state.action = CREATED;
+            });
+            if before_hook().not() {
+                continue;
+            }
             let mut child_process = Command::new(&binary);
             debug(format!("启动命令: {} ", &binary));
             debug(format!("启动参数: {:?} ", &args));
-            let child_process = child_process
-                .current_dir(PathBuf::from(&binary).parent().unwrap())
+            let child_process = policy
+                .apply(&mut child_process)
                 .args(&args)
-                .envs(&envs)
-                .stdout(Stdio::piped())
-                .stderr(Stdio::piped());
+                .env(PROGRAM_ENV, &marker)
+                .stdout(stdio(output.stdout))
+                .stderr(stdio(output.stderr));
             unsafe {
                 child_process.pre_exec(move || {
                     let pid = libc::getpid();
@@ -227,163 +344,203 @@ impl StableWorker {
                     Ok(())
                 });
             }
-            let child_process = child_process.spawn();
+            let child_process = spawn_managed(child_process);
             if let Err(e) = child_process {
-                {
-                    if let Ok(mut lock) = callback_action.lock() {
-                        *lock = EXITED(1);
-                    }
-                }
-                error(format!("项目启动错误！{}", e.to_string()));
+                set_action(EXITED(ExitInfo::code(1)));
+                error(format!(
+                    "[{}] 项目启动错误！{}",
+                    name,
+                    policy.describe_error(&e)
+                ));
                 continue;
             }
 
             let mut child_process = child_process.unwrap();
-            {
-                if let Ok(mut lock) = callback_action.lock() {
-                    *lock = STARTED;
-                }
-            }
+            let child_pid = child_process.id() as i32;
+            update(&|state| {
+                state.pid = Some(child_pid);
+                state.action = STARTED;
+            });
             debug_str("开始抓取进程标准输出信息.");
-            let mut stdout =
-                NonBlockingReader::from_fd(child_process.stdout.take().unwrap()).unwrap();
-            let mut stderr =
-                NonBlockingReader::from_fd(child_process.stderr.take().unwrap()).unwrap();
-            let mut buffer = vec![];
+            let pidfd = PidFd::open(child_process.id() as i32);
+            let generation = shared.status.lock().map(|e| e.launch).unwrap_or(0);
+            let mut stdout = OutputStream::new(
+                &name,
+                STDOUT,
+                output.stdout,
+                child_process.id(),
+                generation,
+                child_process.stdout.take(),
+                output.max_line,
+            );
+            let mut stderr = OutputStream::new(
+                &name,
+                STDERR,
+                output.stderr,
+                child_process.id(),
+                generation,
+                child_process.stderr.take(),
+                output.max_line,
+            );
             'l: loop {
+                trace_str("开始搜集响应日志.");
+                stdout.forward();
+                stderr.forward();
                 if let Ok(Some(code)) = child_process.try_wait() {
-                    let i = code.code().unwrap_or_else(|| 1);
-                    {
-                        if let Ok(mut lock) = callback_action.lock() {
-                            *lock = EXITED(i);
-                        }
-                    }
-                    if i != 0 {
-                        debug_str("程序异常退出");
+                    unregister(child_process.id() as i32);
+                    // 输出退出前剩余的日志
+                    stdout.finish();
+                    stderr.finish();
+                    let exit = ExitInfo::from(code);
+                    debug(format!("[{}] 程序已退出，{}", name, exit));
+                    update(&|state| {
+                        state.pid = None;
+                        state.exit_code = Some(exit.exit_code());
+                    });
+                    destroy_hook(Some(exit));
+                    // 本次运行期间 cgroup 中有进程被 OOM Killer 结束
+                    let oom_killed = policy
+                        .cgroup
+                        .as_ref()
+                        .filter(|e| e.oom_kills() > oom_kills)
+                        .is_some();
+                    if oom_killed && exit.signal == Some(SIGKILL) {
+                        error(format!(
+                            "[{}] 程序因内存超出 cgroup 上限被 OOM Killer 结束",
+                            name
+                        ));
+                        set_action(OOM_KILLED);
                     } else {
-                        debug_str("程序正常退出");
-                    }
-                    destroy_hook();
-                    break;
-                }
-                trace_str("开始搜集响应日志.");
-                thread::sleep(Duration::from_secs(1));
-                buffer.clear();
-                let stdout_size = stdout.read_available(&mut buffer).unwrap();
-                trace_str("抓取标准日志.");
-                let s_out = String::from_utf8_lossy(&buffer[..stdout_size])
-                    .trim()
-                    .to_string();
-                if s_out.is_empty().not() {
-                    info(format!("子进程标准输出:\n{}", s_out));
-                }
-                buffer.clear();
-                trace_str("抓取错误日志.");
-                let stderr_size = stderr.read_available(&mut buffer).unwrap();
-                let s_err = String::from_utf8_lossy(&buffer[..stderr_size])
-                    .trim()
-                    .to_string();
-                if s_err.is_empty().not() {
-                    warn(format!("子进程错误输出:\n{}", s_err));
-                }
-                buffer.clear();
-                trace_str("抓取日志完成.");
-                fn wait_then_kill(child: &mut Child, timeout: i32) {
-                    for index in 0..(timeout * 2) {
-                        if let Ok(Some(code)) = child.try_wait() {
-                            debug(format!(
-                                "程序等待大约 {} 秒后程序停止,停止状态为 {}.",
-                                index / 2,
-                                code.code().unwrap_or(-1)
+                        if oom_killed {
+                            warn(format!(
+                                "[{}] cgroup 中有子进程因内存超出上限被 OOM Killer 结束",
+                                name
                             ));
-                            return;
                         }
-                        thread::sleep(Duration::from_millis(500));
+                        set_action(EXITED(exit));
                     }
-                    warn(format!(
-                        "程序在规定时间内({}s)未停止，将强制杀死进程.",
-                        timeout
-                    ));
-                    child.kill().unwrap_or(());
+                    break;
                 }
                 if let Ok(e) = nio_rx.try_recv() {
                     match e {
                         KILL(i) => {
-                            unsafe {
-                                libc::kill(child_process.id() as i32, i);
-                            }
                             debug(format!(
-                                "程序收到停止指令，停止程序并等待下次唤醒,使用 {} 信号量.",
-                                i
+                                "[{}] 程序收到停止指令，停止程序并等待下次唤醒,使用 {} 信号量.",
+                                name, i
                             ));
-                            destroy_hook();
-                            wait_then_kill(&mut child_process, 90);
-                            debug(format!("等待进程 {} 结束.", child_process.id()));
+                            let mut steps = vec![StopStep {
+                                signal: Signal(i),
+                                wait: stop_steps[0].wait,
+                            }];
+                            steps.extend(stop_steps.iter().cloned());
+                            let exit =
+                                stop_child(&name, &mut child_process, &steps).map(ExitInfo::from);
+                            update(&|state| {
+                                state.pid = None;
+                                state.exit_code = exit.map(|e| e.exit_code());
+                            });
+                            if let Some(cgroup) = &policy.cgroup {
+                                cgroup.kill(&name);
+                            }
+                            stdout.finish();
+                            stderr.finish();
+                            destroy_hook(exit);
                             break 'l;
                         }
                         RESTART => {
-                            unsafe {
-                                libc::kill(child_process.id() as i32, signals.exit);
+                            debug(format!("[{}] 程序收到重启指令，退出程序并重启.", name));
+                            let exit = stop_child(&name, &mut child_process, &stop_steps)
+                                .map(ExitInfo::from);
+                            update(&|state| {
+                                state.pid = None;
+                                state.exit_code = exit.map(|e| e.exit_code());
+                            });
+                            if let Some(cgroup) = &policy.cgroup {
+                                cgroup.kill(&name);
                             }
-                            destroy_hook();
-                            debug(format!(
-                                "程序收到重启指令，退出程序并重启,使用 {} 信号量.",
-                                signals.exit
-                            ));
-                            wait_then_kill(&mut child_process, 90);
+                            stdout.finish();
+                            stderr.finish();
+                            destroy_hook(exit);
                             restart = true;
                             break 'l;
                         }
                         EXIT => {
-                            unsafe {
-                                libc::kill(child_process.id() as i32, signals.exit);
-                            }
-                            destroy_hook();
-                            debug(format!(
-                                "程序收到退出指令，退出程序,使用 {} 信号量.",
-                                signals.exit
-                            ));
-                            wait_then_kill(&mut child_process, 90);
+                            debug(format!("[{}] 程序收到退出指令，退出程序.", name));
+                            let exit = stop_child(&name, &mut child_process, &stop_steps)
+                                .map(ExitInfo::from);
+                            update(&|state| {
+                                state.pid = None;
+                                state.exit_code = exit.map(|e| e.exit_code());
+                            });
+                            stdout.finish();
+                            stderr.finish();
+                            kill_marked(&name, &marker);
+                            destroy_hook(exit);
                             break 'e;
                         }
-                        _ => {}
+                        _ => continue 'l,
                     }
                 }
+                // 等待指令、进程退出或新的输出，内核不支持 pidfd 时定时检查进程状态
+                let mut fds = vec![poll_fd(shared.wake.fd())];
+                if let Some(pidfd) = &pidfd {
+                    fds.push(poll_fd(pidfd.fd()));
+                }
+                for fd in [stdout.fd(), stderr.fd()].into_iter().flatten() {
+                    fds.push(poll_fd(fd));
+                }
+                let timeout = Some(Duration::from_secs(1)).filter(|_| pidfd.is_none());
+                poll(&mut fds, timeout);
+                shared.wake.drain();
             }
         }
-        {
-            if let Ok(mut lock) = callback_action.lock() {
-                *lock = DESTROYED;
-            }
-            debug_str("执行器已被销毁，无法执行新的程序");
+        if let Some(cgroup) = &policy.cgroup {
+            cgroup.kill(&name);
         }
+        set_action(DESTROYED);
+        debug(format!("[{}] 执行器已被销毁，无法执行新的程序", name));
     }
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
+        name: String,
         binary: String,
         args: Vec<String>,
-        envs: HashMap<String, String>,
-        signals: &SoftSignals,
+        policy: ExecPolicy,
+        stop_steps: Vec<StopStep>,
+        output: OutputConfig,
         hooks: HookScripts,
+        notifier: Notifier,
     ) -> Self {
-        let arc = Arc::new(Mutex::new(CREATED));
+        let shared = Arc::new(WorkerShared {
+            status: Mutex::new(WorkerState {
+                launch: 0,
+                action: CREATED,
+                pid: None,
+                exit_code: None,
+            }),
+            changed: Condvar::new(),
+            wake: EventFd::new().expect("无法创建事件通知"),
+            notifier,
+        });
         let to_master: (SyncSender<ChildThreadAction>, Receiver<ChildThreadAction>) =
             mpsc::sync_channel(255);
-        let callback_action = Arc::clone(&arc);
-        let config_signals = signals.clone();
+        let thread_shared = Arc::clone(&shared);
         thread::spawn(move || {
             Self::thread_fun(
                 to_master.1,
+                name,
                 binary,
                 args,
-                envs,
-                callback_action,
-                config_signals,
+                policy,
+                thread_shared,
+                stop_steps,
+                output,
                 hooks,
             );
         });
         let worker = StableWorker {
             master_rx: to_master.0,
-            status: arc,
+            shared,
         };
         return worker;
     }
@@ -393,4 +550,62 @@ pub struct HookScripts {
     pub script_worker: String,
     pub before_script: Option<String>,
     pub after_script: Option<String>,
+    pub policy: ExecPolicy,
+}
+
+/**
+按步骤停止子进程：依次向进程组发送信号并等待，进程退出后记录是哪一步停止了进程，全部步骤完成后仍未退出则强制杀死进程组
+ */
+fn stop_child(name: &str, child: &mut Child, steps: &[StopStep]) -> Option<ExitStatus> {
+    let pid = child.id() as i32;
+    let start = Instant::now();
+    for (index, step) in steps.iter().enumerate() {
+        if let Ok(Some(status)) = child.try_wait() {
+            unregister(pid);
+            return Some(status);
+        }
+        signal_group(pid, step.signal.0);
+        debug(format!(
+            "[{}] 停止步骤 {}: 发送 {} 信号，最多等待 {}.",
+            name,
+            index + 1,
+            signal_name(step.signal.0),
+            step.wait
+        ));
+        let deadline = Instant::now() + step.wait.0;
+        let pidfd = PidFd::open(pid);
+        loop {
+            if let Ok(Some(code)) = child.try_wait() {
+                info(format!(
+                    "[{}] 程序在停止步骤 {} ({}) 后停止,耗时 {:.1} 秒,停止状态为 {}.",
+                    name,
+                    index + 1,
+                    signal_name(step.signal.0),
+                    start.elapsed().as_secs_f64(),
+                    ExitInfo::from(code)
+                ));
+                unregister(pid);
+                return Some(code);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            match &pidfd {
+                Some(pidfd) => {
+                    pidfd.wait(deadline - now);
+                }
+                None => thread::sleep(Duration::from_millis(100)),
+            }
+        }
+    }
+    warn(format!(
+        "[{}] 程序在全部停止步骤后({:.1}s)仍未停止，将强制杀死进程.",
+        name,
+        start.elapsed().as_secs_f64()
+    ));
+    signal_group(pid, libc::SIGKILL);
+    let status = child.wait().ok();
+    unregister(pid);
+    status
 }
@@ -0,0 +1,233 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use std::fs::File;
+use std::io;
+use std::io::Write;
+use std::ops::Not;
+use std::os::unix::io::{AsRawFd, OwnedFd, RawFd};
+use std::process::Stdio;
+
+use nonblock::NonBlockingReader;
+
+use crate::config::prop::LoggerLevel::{INFO, WARN};
+use crate::config::prop::OutputMode;
+use crate::config::prop::OutputMode::{BOTH, CAPTURE, DISCARD, PASSTHROUGH};
+use crate::log::{info, warn};
+use crate::utils::log::file_log;
+
+/// 按行拆分输出，未结束的行会被缓存，超过 `max` 字节的行会被拆分
+pub struct LineBuffer {
+    pending: Vec<u8>,
+    max: usize,
+}
+
+impl LineBuffer {
+    pub fn new(max: usize) -> Self {
+        LineBuffer {
+            pending: vec![],
+            max: max.max(1),
+        }
+    }
+
+    /// 追加数据，返回已完整的行
+    pub fn push(&mut self, data: &[u8]) -> Vec<String> {
+        let mut lines = vec![];
+        for byte in data {
+            if *byte == b'\n' {
+                lines.push(self.take(self.pending.len()));
+                continue;
+            }
+            self.pending.push(*byte);
+            if self.pending.len() >= self.max {
+                lines.push(self.take(char_boundary(&self.pending)));
+            }
+        }
+        lines
+    }
+
+    /// 取出剩余未结束的行
+    pub fn flush(&mut self) -> Option<String> {
+        Some(self.take(self.pending.len())).filter(|e| e.is_empty().not())
+    }
+
+    fn take(&mut self, size: usize) -> String {
+        let line: Vec<u8> = self.pending.drain(..size).collect();
+        let line = line.strip_suffix(b"\r").unwrap_or(&line);
+        String::from_utf8_lossy(line).to_string()
+    }
+}
+
+/// 拆分位置，最后一个 UTF-8 字符不完整时在该字符之前拆分
+fn char_boundary(data: &[u8]) -> usize {
+    let start = data.iter().rposition(|e| (e & 0xC0) != 0x80).unwrap_or(0);
+    let width = match data[start] {
+        b if b >= 0xF0 => 4,
+        b if b >= 0xE0 => 3,
+        b if b >= 0xC0 => 2,
+        _ => 1,
+    };
+    if start > 0 && start + width > data.len() {
+        start
+    } else {
+        data.len()
+    }
+}
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum StreamKind {
+    STDOUT,
+    STDERR,
+}
+
+impl StreamKind {
+    fn name(&self) -> &'static str {
+        match self {
+            StreamKind::STDOUT => "stdout",
+            StreamKind::STDERR => "stderr",
+        }
+    }
+}
+
+/// 子进程输出流的来源：继承、丢弃或通过管道读取
+pub fn stdio(mode: OutputMode) -> Stdio {
+    match mode {
+        PASSTHROUGH => Stdio::inherit(),
+        DISCARD => Stdio::null(),
+        CAPTURE | BOTH => Stdio::piped(),
+    }
+}
+
+/**
+子进程的单个输出流，逐行转发到日志，每行带有流名称、进程号与启动次数
+
+`BOTH` 模式下输出原样写入控制台，按行的副本只写入文件日志
+ */
+pub struct OutputStream {
+    name: String,
+    kind: StreamKind,
+    mode: OutputMode,
+    pid: u32,
+    generation: u64,
+    fd: RawFd,
+    reader: Option<NonBlockingReader<File>>,
+    lines: LineBuffer,
+    buffer: Vec<u8>,
+}
+
+impl OutputStream {
+    pub fn new(
+        name: &str,
+        kind: StreamKind,
+        mode: OutputMode,
+        pid: u32,
+        generation: u64,
+        source: Option<impl Into<OwnedFd>>,
+        max_line: usize,
+    ) -> Self {
+        // 未使用管道 (继承或丢弃) 时没有可读取的输出
+        let file = source.map(|e| File::from(e.into()));
+        let fd = file.as_ref().map(|e| e.as_raw_fd()).unwrap_or(-1);
+        OutputStream {
+            name: name.to_string(),
+            kind,
+            mode,
+            pid,
+            generation,
+            fd,
+            reader: file.and_then(|e| NonBlockingReader::from_fd(e).ok()),
+            lines: LineBuffer::new(max_line),
+            buffer: vec![],
+        }
+    }
+
+    /// 需要等待的文件描述符，流结束后为 `None`
+    pub fn fd(&self) -> Option<RawFd> {
+        self.reader.as_ref().map(|_| self.fd)
+    }
+
+    /// 读取当前可用的输出并转发完整的行
+    pub fn forward(&mut self) {
+        let reader = match self.reader.as_mut() {
+            Some(reader) => reader,
+            None => return,
+        };
+        self.buffer.clear();
+        let size = reader.read_available(&mut self.buffer).unwrap_or(0);
+        if reader.is_eof() {
+            self.reader = None;
+        }
+        if self.mode == BOTH && size > 0 {
+            let data = &self.buffer[..size];
+            match self.kind {
+                StreamKind::STDOUT => io::stdout()
+                    .write_all(data)
+                    .and_then(|_| io::stdout().flush()),
+                StreamKind::STDERR => io::stderr().write_all(data),
+            }
+            .ok();
+        }
+        for line in self.lines.push(&self.buffer[..size]) {
+            self.write(&line);
+        }
+    }
+
+    /// 转发剩余的全部输出，包括未结束的行
+    pub fn finish(&mut self) {
+        self.forward();
+        if let Some(line) = self.lines.flush() {
+            self.write(&line);
+        }
+    }
+
+    fn write(&self, line: &str) {
+        let message = format!(
+            "[{}] {}(pid={}, gen={}): {}",
+            self.name,
+            self.kind.name(),
+            self.pid,
+            self.generation,
+            line
+        );
+        match (self.mode, self.kind) {
+            (BOTH, StreamKind::STDOUT) => file_log(INFO, message),
+            (BOTH, StreamKind::STDERR) => file_log(WARN, message),
+            (_, StreamKind::STDOUT) => info(message),
+            (_, StreamKind::STDERR) => warn(message),
+        }
+    }
+}
+
+#[test]
+fn line_buffer_test() {
+    let mut buffer = LineBuffer::new(8);
+    assert_eq!(buffer.push(b"hello\nwor"), vec!["hello"]);
+    assert_eq!(buffer.push(b"ld\r\n"), vec!["world"]);
+    assert_eq!(buffer.push(b"0123456789\n"), vec!["01234567", "89"]);
+    assert_eq!(buffer.push(b"tail"), Vec::<String>::new());
+    assert_eq!(buffer.flush(), Some("tail".to_string()));
+    assert_eq!(buffer.flush(), None);
+    // 不拆开多字节字符
+    let mut buffer = LineBuffer::new(4);
+    assert_eq!(buffer.push("a中文\n".as_bytes()), vec!["a中", "文"]);
+    assert_eq!(buffer.push("ab中\n".as_bytes()), vec!["ab", "中"]);
+}
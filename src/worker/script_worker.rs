@@ -20,25 +20,27 @@
  * SOFTWARE.
  */
 
-use std::collections::HashMap;
 use std::ops::Not;
 use std::process::Command;
-use std::sync::atomic::{AtomicBool, Ordering};
-use std::sync::mpsc::{Receiver, SyncSender};
-use std::sync::{mpsc, Arc};
-use std::time::Duration;
+use std::sync::mpsc::{Receiver, RecvTimeoutError, SyncSender};
+use std::sync::{mpsc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
 use std::{fs, thread};
 
 use crate::lib::SoftError;
 use crate::log::{debug, error};
+use crate::utils::command::ExecPolicy;
+use crate::utils::event::Notifier;
 use crate::utils::file::new_temp_path;
+use crate::utils::process::output_managed;
 use crate::worker::script_worker::WorkerAction::{EXIT, START, STOP};
 use crate::{debug_str, log};
 
 pub struct ScriptWorker {
     receiver: Receiver<usize>,
     sender: SyncSender<WorkerAction>,
-    exited: Arc<AtomicBool>,
+    handle: Mutex<Option<JoinHandle<()>>>,
 }
 
 impl ScriptWorker {
@@ -69,8 +71,9 @@ impl ScriptWorker {
         self.sender.send(EXIT).unwrap();
     }
     pub fn wait_closed(&self) {
-        while self.exited.load(Ordering::Relaxed).not() {
-            thread::sleep(Duration::from_millis(100));
+        let handle = self.handle.lock().ok().and_then(|mut e| e.take());
+        if let Some(handle) = handle {
+            handle.join().ok();
         }
     }
 }
@@ -84,12 +87,12 @@ pub enum WorkerAction {
 
 struct ScriptThreadInfo {
     pub interpreter: String,
-    pub envs: HashMap<String, String>,
+    pub policy: ExecPolicy,
     pub delay: usize,
     pub interval: usize,
     pub sender: SyncSender<usize>,
     pub script_path: String,
-    pub exited: Arc<AtomicBool>,
+    pub notifier: Notifier,
     receiver: Receiver<WorkerAction>,
     pub name: String,
 }
@@ -99,38 +102,41 @@ struct ScriptThreadInfo {
  **/
 impl ScriptWorker {
     fn thread_fun(info: ScriptThreadInfo) {
-        'a: loop {
-            if let Ok(i) = info.receiver.try_recv() {
-                if i == EXIT {
-                    break;
-                } else if i == STOP {
-                    debug_str("检测任务暂停，等待下次唤醒");
-                    'b: loop {
-                        if let Ok(data) = info.receiver.try_recv() {
-                            match data {
-                                START => {
-                                    debug_str("收到开始指令");
-                                    break 'b;
-                                }
-                                STOP => continue,
-                                EXIT => break 'a,
-                            };
-                        } else {
-                            thread::sleep(Duration::from_millis(100));
-                        }
-                    }
-                    debug_str("任务已被唤醒");
-                    thread::sleep(Duration::from_secs(info.delay as u64));
-                } else if i == START {
+        // 下次执行脚本的时间，为 None 时任务暂停
+        let mut next_run: Option<Instant> = None;
+        loop {
+            let action = match next_run {
+                None => match info.receiver.recv() {
+                    Ok(action) => Some(action),
+                    Err(_) => break,
+                },
+                Some(at) => match info
+                    .receiver
+                    .recv_timeout(at.saturating_duration_since(Instant::now()))
+                {
+                    Ok(action) => Some(action),
+                    Err(RecvTimeoutError::Timeout) => None,
+                    Err(RecvTimeoutError::Disconnected) => break,
+                },
+            };
+            match action {
+                Some(START) => {
                     debug_str("任务已被唤醒");
-                    thread::sleep(Duration::from_secs(info.delay as u64));
+                    next_run = Some(Instant::now() + Duration::from_secs(info.delay as u64));
+                    continue;
                 }
+                Some(STOP) => {
+                    debug_str("检测任务暂停，等待下次唤醒");
+                    next_run = None;
+                    continue;
+                }
+                Some(EXIT) => break,
+                None => {}
             }
-            if let Ok(data) = Command::new(&info.interpreter)
-                .arg(&info.script_path)
-                .envs(&info.envs)
-                .output()
-            {
+            if let Ok(data) = output_managed(
+                info.policy
+                    .apply(Command::new(&info.interpreter).arg(&info.script_path)),
+            ) {
                 for x in Some(String::from_utf8_lossy(&data.stdout).to_string())
                     .filter(|e| e.is_empty().not())
                 {
@@ -155,48 +161,47 @@ impl ScriptWorker {
                     debug(format!("{}执行结束，退出状态正常。", info.name));
                     info.sender.send(0).unwrap();
                 }
+                info.notifier.notify();
             }
-
-            thread::sleep(Duration::from_secs(info.interval as u64));
+            next_run = Some(Instant::now() + Duration::from_secs(info.interval as u64));
         }
-        info.exited.swap(true, Ordering::Release);
     }
     pub fn new(
         name: &str,
         interpreter: &String,
         script: &String,
-        envs: &HashMap<String, String>,
+        policy: &ExecPolicy,
         delay: usize,
         interval: usize,
+        notifier: &Notifier,
     ) -> Result<Self, SoftError> {
         let name = name.to_string();
         let to_master: (SyncSender<usize>, Receiver<usize>) = mpsc::sync_channel(255);
         let to_thread: (SyncSender<WorkerAction>, Receiver<WorkerAction>) = mpsc::sync_channel(255);
         let worker_script_path = new_temp_path("args-worker-script");
         fs::write(&worker_script_path, script)?;
+        policy.own(&worker_script_path);
         let to_master_sender = to_master.0;
         let to_master_receiver = to_master.1;
         let to_thread_sender = to_thread.0;
         let to_thread_receiver = to_thread.1;
         let script_path = worker_script_path.as_path().to_str().unwrap().to_string();
-        let arc = Arc::new(AtomicBool::new(false));
-        let arc1 = Arc::clone(&arc);
         let worker = ScriptThreadInfo {
             name,
-            exited: arc,
+            notifier: notifier.clone(),
             interpreter: interpreter.clone(),
-            envs: envs.clone(),
+            policy: policy.clone(),
             delay,
             interval,
             sender: to_master_sender,
             receiver: to_thread_receiver,
             script_path,
         };
-        thread::spawn(|| -> () { Self::thread_fun(worker) });
+        let handle = thread::spawn(|| -> () { Self::thread_fun(worker) });
         Ok(ScriptWorker {
             receiver: to_master_receiver,
             sender: to_thread_sender,
-            exited: arc1,
+            handle: Mutex::new(Some(handle)),
         })
     }
 }
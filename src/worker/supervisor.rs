@@ -0,0 +1,1238 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use std::collections::HashMap;
+use std::fs;
+use std::ops::Not;
+use std::path::PathBuf;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use chrono::{Local, Utc};
+use libc::{c_int, pid_t, SIGKILL};
+use serde_json::Value;
+
+use crate::binary::args_builder::BinaryContext;
+use crate::config::prop::ExitClass::{FAILURE, FATAL, SUCCESS};
+use crate::config::prop::ProgramInfo;
+use crate::config::prop::RestartPolicy::{FAIL, NONE};
+use crate::config::prop::{
+    ExitClass, ProjectInfo, RestartBackoff, ScheduleTimezone, SighupAction, WatchdogAction,
+};
+use crate::lib::SoftError;
+use crate::log::{debug, error, info, warn};
+use crate::utils::cgroup::Cgroup;
+use crate::utils::command::{execute_script, ExecPolicy};
+use crate::utils::event::Notifier;
+use crate::utils::log;
+use crate::utils::process::signal_group;
+use crate::utils::signal::signal_name;
+use crate::utils::string::replace_all_str_from_map;
+use crate::utils::watch::FileWatcher;
+use crate::worker::binary_worker::CallbackAction::{EXITED, OOM_KILLED, STARTED};
+use crate::worker::binary_worker::{ExitInfo, HookScripts, StableWorker};
+use crate::worker::script_worker::ScriptWorker;
+use crate::worker::supervisor::DependState::{BROKEN, READY, WAITING};
+use crate::worker::supervisor::ProgramState::{FAILED, FINISHED, PENDING, RUNNING};
+use crate::worker::watchdog::Watchdog;
+
+/// 程序在 `start_limit_interval` 内启动次数超过 `start_limit_burst` 时的退出码 (EX_TEMPFAIL)
+pub const EXIT_START_LIMIT: i32 = 75;
+
+/// 配置文件、启动参数或执行环境的配置错误时的退出码 (EX_CONFIG)
+pub const EXIT_CONFIG: i32 = 78;
+
+/// 启动前脚本执行失败时程序的退出码 (EX_UNAVAILABLE)
+pub const EXIT_BEFORE_SCRIPT: i32 = 69;
+
+/// 内部错误时的退出码，如无法输出 JSON Schema (EX_SOFTWARE)
+pub const EXIT_SOFTWARE: i32 = 70;
+
+/// 运行环境的系统错误时的退出码，如无法创建 cgroup (EX_OSERR)
+pub const EXIT_OSERR: i32 = 71;
+
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum ProgramState {
+    /// 等待依赖启动
+    PENDING,
+    RUNNING,
+    /// 根据重启策略已结束
+    FINISHED,
+    /// 启动次数超出限制，不再重启
+    FAILED,
+}
+
+/// 依赖的程序对当前程序启动的影响
+#[derive(PartialEq, Debug, Copy, Clone)]
+pub enum DependState {
+    /// 依赖尚未启动完成
+    WAITING,
+    /// 依赖已启动完成或已成功结束
+    READY,
+    /// 依赖已失败、不可恢复地退出或未启动完成即已结束，当前程序不再启动
+    BROKEN,
+}
+
+impl DependState {
+    /// 合并全部依赖的状态，任一依赖无法满足时当前程序不再启动
+    pub fn all(states: impl IntoIterator<Item = DependState>) -> DependState {
+        let mut result = READY;
+        for state in states {
+            match state {
+                BROKEN => return BROKEN,
+                WAITING => result = WAITING,
+                READY => {}
+            }
+        }
+        result
+    }
+}
+
+/**
+单个程序的守护者，负责程序的启动、检查与按策略重启
+ */
+pub struct ProgramSupervisor {
+    pub name: String,
+    pub depends_on: Vec<String>,
+    project: ProjectInfo,
+    context: BinaryContext,
+    /// 程序与脚本的执行环境
+    policy: ExecPolicy,
+    script_policy: ExecPolicy,
+    notifier: Notifier,
+    worker: Option<StableWorker>,
+    health_check: Option<ScriptWorker>,
+    started_check: Option<ScriptWorker>,
+    started_success: i32,
+    health_fail: i32,
+    /// 已下发的启动次数
+    launches: u64,
+    /// 已处理退出状态的启动次数
+    handled: u64,
+    /// 是否已启动完成，存在启动检查时以检查通过为准
+    started: bool,
+    state: ProgramState,
+    /// 连续重启次数，用于计算退避延时
+    attempts: u32,
+    /// 限制时间内的启动时间
+    starts: Vec<Instant>,
+    launched_at: Instant,
+    restart_at: Option<Instant>,
+    /// 配置已变更，下次重启时重新创建工作线程
+    stale: bool,
+    watcher: Option<FileWatcher>,
+    /// 尚未处理的监听文件变化
+    watch_changed: Vec<PathBuf>,
+    watch_at: Option<Instant>,
+    /// 等待文件稳定时上次检查的文件状态
+    watch_snapshot: Option<Vec<FileState>>,
+    /// 下次定时重启的时间
+    schedule_at: Option<Instant>,
+    /// 运行时间达到 `max_uptime` 的时间
+    uptime_at: Option<Instant>,
+    /// 最近一次退出的状态，传递给 `failure_script`
+    last_exit: Option<ExitInfo>,
+    /// 最近一次退出的分类
+    last_class: Option<ExitClass>,
+    watchdog: Option<Watchdog>,
+    /// 下次资源采样的时间
+    watchdog_at: Option<Instant>,
+}
+
+/// 文件的大小与修改时间，文件不存在时为 `None`
+type FileState = Option<(u64, SystemTime)>;
+
+/// 变化时需要重启程序的配置项：可执行文件、钩子脚本与执行环境
+const RESTART_FIELDS: [&str; 19] = [
+    "binary",
+    "before_script",
+    "after_script",
+    "script_worker",
+    "script_policy",
+    "workdir",
+    "umask",
+    "clear_env",
+    "env_allow",
+    "env_deny",
+    "env",
+    "user",
+    "group",
+    "supplementary_groups",
+    "limits",
+    "nice",
+    "ionice",
+    "oom_score_adj",
+    "output",
+];
+
+/// 变化后立即生效的配置项，其余配置项在程序下次重启时生效
+const LIVE_FIELDS: [&str; 14] = [
+    "restart_policy",
+    "success_exit_codes",
+    "fatal_exit_codes",
+    "on_signal",
+    "restart_backoff",
+    "restart_schedule",
+    "max_uptime",
+    "start_limit_burst",
+    "start_limit_interval",
+    "failure_script",
+    "reload_script",
+    "sighup_action",
+    "watch",
+    "watchdog",
+];
+
+/**
+解析后的程序配置，重新加载配置时先校验全部程序，再逐个应用到 [`ProgramSupervisor`]
+ */
+pub struct ProgramSpec {
+    project: ProjectInfo,
+    depends_on: Vec<String>,
+    context: BinaryContext,
+    policy: ExecPolicy,
+    script_policy: ExecPolicy,
+}
+
+impl ProgramSpec {
+    pub fn new(program: &ProgramInfo, context: BinaryContext) -> Result<Self, SoftError> {
+        let mut project = program.project.clone();
+        // 脚本内容替换
+        replace_all_str_from_map(&mut project.before_script, &context.script_vars);
+        replace_all_str_from_map(&mut project.after_script, &context.script_vars);
+        replace_all_str_from_map(&mut project.check_health.script, &context.script_vars);
+        replace_all_str_from_map(&mut project.check_started.script, &context.script_vars);
+        replace_all_str_from_map(
+            &mut project.check_started.started_script,
+            &context.script_vars,
+        );
+        replace_all_str_from_map(&mut project.failure_script, &context.script_vars);
+        replace_all_str_from_map(&mut project.reload_script, &context.script_vars);
+        replace_all_str_from_map(&mut project.watchdog.script, &context.script_vars);
+        let policy = ExecPolicy::program(&project, &context)?;
+        let script_policy = ExecPolicy::script(&project, &context)?;
+        Ok(ProgramSpec {
+            depends_on: program.depends_on.clone(),
+            project,
+            context,
+            policy,
+            script_policy,
+        })
+    }
+}
+
+impl ProgramSupervisor {
+    /// 按已校验的配置创建监控，启用 cgroup 时同时创建程序的 cgroup
+    pub fn new(mut spec: ProgramSpec, notifier: &Notifier) -> Result<Self, SoftError> {
+        spec.policy.cgroup = Cgroup::create(&spec.project.name, &spec.project.cgroup)?;
+        let mut supervisor = ProgramSupervisor {
+            name: spec.project.name.to_string(),
+            depends_on: spec.depends_on,
+            project: spec.project,
+            context: spec.context,
+            policy: spec.policy,
+            script_policy: spec.script_policy,
+            notifier: notifier.clone(),
+            worker: None,
+            health_check: None,
+            started_check: None,
+            started_success: 0,
+            health_fail: 0,
+            launches: 0,
+            handled: 0,
+            started: false,
+            state: PENDING,
+            attempts: 0,
+            starts: vec![],
+            launched_at: Instant::now(),
+            restart_at: None,
+            stale: false,
+            watcher: None,
+            watch_changed: vec![],
+            watch_at: None,
+            watch_snapshot: None,
+            schedule_at: None,
+            uptime_at: None,
+            last_exit: None,
+            last_class: None,
+            watchdog: None,
+            watchdog_at: None,
+        };
+        supervisor.watcher = supervisor.create_watcher();
+        supervisor.watchdog = supervisor.create_watchdog();
+        Ok(supervisor)
+    }
+
+    /// 按 `watch` 配置监听文件，未配置时返回 `None`
+    fn create_watcher(&self) -> Option<FileWatcher> {
+        let watch = &self.project.watch;
+        if watch.is_enabled().not() {
+            return None;
+        }
+        let base = self.policy.workdir.clone().unwrap_or_default();
+        let mut paths: Vec<PathBuf> = watch.paths.iter().map(|e| base.join(e)).collect();
+        if watch.binary {
+            paths.push(PathBuf::from(&self.project.binary));
+        }
+        match FileWatcher::new(&paths, &watch.exclude, &self.notifier) {
+            Ok(watcher) => Some(watcher),
+            Err(e) => {
+                warn(format!(
+                    "[{}] 无法监听文件，watch 不会生效: {}",
+                    self.name, e
+                ));
+                None
+            }
+        }
+    }
+
+    /// 按 `watchdog` 配置创建资源看门狗，未配置阈值时返回 `None`
+    fn create_watchdog(&self) -> Option<Watchdog> {
+        Some(&self.project.watchdog)
+            .filter(|e| e.is_enabled())
+            .map(Watchdog::new)
+    }
+
+    /// 按 `restart_schedule` 计算下次定时重启的时间，未配置时返回 `None`
+    fn next_schedule(&self) -> Option<Instant> {
+        let schedule = &self.project.restart_schedule;
+        let cron = schedule.cron.as_ref()?;
+        let wait = match schedule.timezone {
+            ScheduleTimezone::LOCAL => {
+                let now = Local::now();
+                cron.next_after(&now)? - now
+            }
+            ScheduleTimezone::UTC => {
+                let now = Utc::now();
+                cron.next_after(&now)? - now
+            }
+        };
+        let wait = wait.to_std().unwrap_or_default() + jitter_delay(schedule.jitter.0);
+        debug(format!(
+            "[{}] 下次定时重启将在 {:.0} 秒后.",
+            self.name,
+            wait.as_secs_f64()
+        ));
+        Some(Instant::now() + wait)
+    }
+
+    /// 按 `max_uptime` 计算程序运行时间达到上限的时间，未配置时返回 `None`
+    fn next_uptime(&self) -> Option<Instant> {
+        let max_uptime = self.project.max_uptime?.0;
+        Some(self.launched_at + max_uptime + jitter_delay(self.project.restart_schedule.jitter.0))
+    }
+
+    /// 是否已经启动过，程序启动后依赖它的程序才会启动
+    pub fn is_launched(&self) -> bool {
+        self.worker.is_some()
+    }
+
+    /// 作为依赖时的状态，未启动完成但已成功结束的程序同样视为满足依赖，
+    /// 已失败或不可恢复地退出的程序即使曾启动完成也不再满足依赖
+    pub fn depend_state(&self) -> DependState {
+        depend_state(self.state, self.started, self.last_class)
+    }
+
+    /// 依赖无法满足，不再启动程序并视为已结束
+    pub fn skip(&mut self, depend: &str) {
+        error(format!(
+            "[{}] 依赖的程序 {} 已失败或未启动完成即已结束，不再启动.",
+            self.name, depend
+        ));
+        self.state = FINISHED;
+    }
+
+    /// 下次需要处理的时间，例如延时重启
+    pub fn next_deadline(&self) -> Option<Instant> {
+        if self.state != RUNNING {
+            return None;
+        }
+        [
+            self.restart_at,
+            self.watch_at,
+            self.schedule_at,
+            self.uptime_at,
+            self.watchdog_at,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+
+    /// 是否已根据重启策略结束或已失败
+    pub fn is_finished(&self) -> bool {
+        self.state == FINISHED || self.state == FAILED
+    }
+
+    pub fn is_failed(&self) -> bool {
+        self.state == FAILED
+    }
+
+    /// 程序最近一次退出的退出码，被信号结束时为 128 + 信号量，启动前脚本失败时为 [`EXIT_BEFORE_SCRIPT`]
+    pub fn exit_code(&self) -> Option<i32> {
+        self.worker.as_ref().and_then(|e| e.state().exit_code)
+    }
+
+    /// 程序最近一次异常退出的退出码，按 `success_exit_codes` 与 `on_signal` 视为正常退出时为 `None`
+    pub fn failure_exit_code(&self) -> Option<i32> {
+        self.last_exit
+            .filter(|_| self.last_class != Some(SUCCESS))
+            .map(|e| e.exit_code())
+    }
+
+    /// 程序主进程的进程号，同时也是进程组号
+    pub fn pid(&self) -> Option<pid_t> {
+        self.worker.as_ref().and_then(|e| e.state().pid)
+    }
+
+    /// 向正在运行的程序的进程组转发信号
+    pub fn forward(&self, signal: c_int) {
+        if let Some(pid) = self.pid() {
+            debug(format!(
+                "[{}] 转发信号 {} 至进程组 {}",
+                self.name,
+                signal_name(signal),
+                pid
+            ));
+            signal_group(pid, signal);
+        }
+    }
+
+    pub fn launch(&mut self) {
+        self.state = RUNNING;
+        self.record_start();
+        self.schedule_at = self.next_schedule();
+        self.watchdog_at = self.next_sample();
+        self.spawn_workers();
+    }
+
+    /// 创建工作线程与检查任务并启动程序
+    fn spawn_workers(&mut self) {
+        let project = &self.project;
+        let worker = StableWorker::new(
+            project.name.to_string(),
+            project.binary.to_owned(),
+            self.context.args.clone(),
+            self.policy.clone(),
+            project.stop_steps(),
+            project.output.clone(),
+            HookScripts {
+                script_worker: project.script_worker.clone(),
+                before_script: Some(project.before_script.clone()).filter(|e| e.is_empty().not()),
+                after_script: Some(project.after_script.clone()).filter(|e| e.is_empty().not()),
+                policy: self.script_policy.clone(),
+            },
+            self.notifier.clone(),
+        ); // 主要进程工作区
+        self.health_check = Some(&project.check_health.script)
+            .filter(|_| project.check_health.interval != 0)
+            .filter(|e| e.is_empty().not())
+            .map(|e| {
+                ScriptWorker::new(
+                    &format!("{} 健康检查任务", project.name),
+                    &project.script_worker,
+                    e,
+                    &self.script_policy,
+                    project.check_health.delay,
+                    project.check_health.interval,
+                    &self.notifier,
+                )
+            })
+            .filter(|e| e.is_ok())
+            .map(|e| e.unwrap());
+        self.started_check = Some(&project.check_started.script)
+            .filter(|_| project.check_started.interval != 0)
+            .filter(|e| e.is_empty().not())
+            .map(|e| {
+                ScriptWorker::new(
+                    &format!("{} 启动完成检查任务", project.name),
+                    &project.script_worker,
+                    e,
+                    &self.script_policy,
+                    0,
+                    project.check_started.interval,
+                    &self.notifier,
+                )
+            })
+            .filter(|e| e.is_ok())
+            .map(|e| e.unwrap());
+        info(format!("[{}] 程序开始启动.", self.name));
+        worker.start();
+        self.worker = Some(worker);
+        self.launches += 1;
+        self.launched_at = Instant::now();
+        self.uptime_at = self.next_uptime();
+        self.enable_check();
+    }
+
+    /// 立即重启程序，不计入启动次数限制，`reason` 为记录在日志中的重启原因
+    pub fn restart(&mut self, reason: &str) {
+        if self.state == RUNNING {
+            info(format!("[{}] 重启程序，原因: {}.", self.name, reason));
+            self.restart_at = None;
+            self.restart_now();
+        }
+    }
+
+    /// 收到 SIGHUP 时按 `sighup_action` 重启或重新加载程序
+    pub fn hangup(&mut self) {
+        match self.project.sighup_action {
+            SighupAction::RESTART => self.restart("sighup"),
+            SighupAction::RELOAD => self.reload(),
+        }
+    }
+
+    /**
+    重新加载程序：执行 `reload_script` 后向程序发送 `signals.reload`，程序不停止，检查计数也不会重置
+     */
+    pub fn reload(&mut self) {
+        if self.state != RUNNING {
+            return;
+        }
+        let pid = match self.worker.as_ref().and_then(|e| e.state().pid) {
+            Some(pid) => pid,
+            None => {
+                debug(format!("[{}] 程序未在运行，忽略重新加载.", self.name));
+                return;
+            }
+        };
+        if self.project.reload_script.trim().is_empty().not() {
+            let mut policy = self.script_policy.clone();
+            policy.envs.insert("MAINPID".to_string(), pid.to_string());
+            match execute_script(
+                "重新加载钩子",
+                &self.project.script_worker,
+                &self.project.reload_script,
+                &policy,
+            ) {
+                Ok(0) => {}
+                _ => {
+                    error(format!(
+                        "[{}] 重新加载脚本执行失败，不发送重新加载信号.",
+                        self.name
+                    ));
+                    return;
+                }
+            }
+        }
+        let signal = self.project.signals.reload;
+        info(format!(
+            "[{}] 发送 {} 重新加载程序.",
+            self.name,
+            signal_name(signal)
+        ));
+        unsafe {
+            libc::kill(pid, signal);
+        }
+    }
+
+    fn restart_now(&mut self) {
+        if self.stale {
+            self.relaunch();
+            return;
+        }
+        if let Some(worker) = &self.worker {
+            worker.restart();
+            self.launches += 1;
+            self.launched_at = Instant::now();
+            self.uptime_at = self.next_uptime();
+            self.enable_check();
+        }
+    }
+
+    /// 记录一次启动，返回是否仍在启动次数限制内
+    fn record_start(&mut self) -> bool {
+        if self.project.start_limit_burst == 0 {
+            return true;
+        }
+        let now = Instant::now();
+        let interval = Duration::from_secs(self.project.start_limit_interval as u64);
+        self.starts.retain(|e| now.duration_since(*e) < interval);
+        self.starts.push(now);
+        self.starts.len() <= self.project.start_limit_burst
+    }
+
+    /// 按退避策略延时重启，超出启动次数限制时进入失败状态
+    fn schedule_restart(&mut self) {
+        if self.record_start().not() {
+            self.fail();
+            return;
+        }
+        let delay = backoff_delay(&self.project.restart_backoff, self.attempts, random_seed());
+        self.attempts += 1;
+        if delay.is_zero() {
+            self.restart_now();
+        } else {
+            info(format!(
+                "[{}] 程序将在 {:.1} 秒后重启.",
+                self.name,
+                delay.as_secs_f64()
+            ));
+            self.restart_at = Some(Instant::now() + delay);
+        }
+    }
+
+    fn fail(&mut self) {
+        error(format!(
+            "[{}] 程序在 {} 秒内启动超过 {} 次，不再重启.",
+            self.name, self.project.start_limit_interval, self.project.start_limit_burst
+        ));
+        self.state = FAILED;
+        self.restart_at = None;
+        self.stop_check();
+        if let Some(worker) = &self.worker {
+            worker.exit();
+        }
+        if self.project.failure_script.is_empty().not() {
+            let mut policy = self.script_policy.clone();
+            if let Some(exit) = self.last_exit {
+                policy
+                    .envs
+                    .extend(exit.envs().map(|(key, value)| (key.to_string(), value)));
+            }
+            match execute_script(
+                "失败回调钩子",
+                &self.project.script_worker,
+                &self.project.failure_script,
+                &policy,
+            ) {
+                Ok(0) => info(format!("[{}] 失败回调执行完成。", self.name)),
+                _ => error(format!("[{}] 失败回调执行失败。", self.name)),
+            }
+        }
+    }
+
+    fn stop_check(&self) {
+        if let Some(started_check) = &self.started_check {
+            started_check.stop()
+        }
+        if let Some(health_check) = &self.health_check {
+            health_check.stop()
+        }
+    }
+
+    fn enable_check(&mut self) {
+        debug(format!("[{}] 开始重置", self.name));
+        self.started_success = 0;
+        self.health_fail = 0;
+        if let Some(watchdog) = &mut self.watchdog {
+            watchdog.reset();
+        }
+        if let Some(started_check) = &self.started_check {
+            started_check.start()
+        }
+        if let Some(health_check) = &self.health_check {
+            health_check.start()
+        }
+    }
+
+    /**
+    处理监听文件的变化：最后一次变化后等待 `debounce`，配置了 `wait_stable` 时等待文件不再变化，再重启程序
+
+    返回是否已重启程序
+     */
+    fn check_watch(&mut self) -> bool {
+        let changed = self
+            .watcher
+            .as_ref()
+            .map(|e| e.changed())
+            .unwrap_or_default();
+        if changed.is_empty().not() {
+            for path in changed {
+                if self.watch_changed.contains(&path).not() {
+                    self.watch_changed.push(path);
+                }
+            }
+            self.watch_at = Some(Instant::now() + self.project.watch.debounce.0);
+            self.watch_snapshot = None;
+        }
+        match self.watch_at {
+            Some(watch_at) if Instant::now() >= watch_at => {}
+            _ => return false,
+        }
+        let wait = self.project.watch.wait_stable.0;
+        if wait.is_zero().not() {
+            let snapshot: Vec<FileState> = self.watch_changed.iter().map(file_state).collect();
+            if self.watch_snapshot.as_ref() != Some(&snapshot) {
+                debug(format!("[{}] 等待文件停止变化...", self.name));
+                self.watch_snapshot = Some(snapshot);
+                self.watch_at = Some(Instant::now() + wait);
+                return false;
+            }
+        }
+        self.watch_at = None;
+        self.watch_snapshot = None;
+        let changed: Vec<PathBuf> = self.watch_changed.drain(..).collect();
+        info(format!("[{}] 监听的文件发生变化: {:?}", self.name, changed));
+        self.restart("watch");
+        true
+    }
+
+    /**
+    处理定时重启与 `max_uptime`，程序正在等待重启时不再重复重启
+
+    返回是否已重启程序
+     */
+    fn check_schedule(&mut self) -> bool {
+        let now = Instant::now();
+        if self.schedule_at.filter(|e| now >= *e).is_some() {
+            self.schedule_at = self.next_schedule();
+            if self.restart_at.is_none() {
+                let cron = self
+                    .project
+                    .restart_schedule
+                    .cron
+                    .as_ref()
+                    .map(|e| e.to_string())
+                    .unwrap_or_default();
+                info(format!("[{}] 到达定时重启时间 ({}).", self.name, cron));
+                self.restart(&format!("scheduled (cron {})", cron));
+                return true;
+            }
+        }
+        if self.uptime_at.filter(|e| now >= *e).is_some() {
+            self.uptime_at = None;
+            if self.restart_at.is_none() {
+                info(format!(
+                    "[{}] 程序运行时间超过 max_uptime ({}).",
+                    self.name,
+                    self.project.max_uptime.unwrap()
+                ));
+                self.restart("scheduled (max_uptime)");
+                return true;
+            }
+        }
+        false
+    }
+
+    /// 下次资源采样的时间，未配置看门狗时返回 `None`
+    fn next_sample(&self) -> Option<Instant> {
+        self.watchdog
+            .as_ref()
+            .map(|_| Instant::now() + self.project.watchdog.interval.0)
+    }
+
+    /**
+    到达采样时间时采样程序主进程的资源占用，超出阈值时按 `watchdog.action` 处理
+
+    返回是否已重启程序
+     */
+    fn check_watchdog(&mut self) -> bool {
+        match self.watchdog_at {
+            Some(at) if Instant::now() >= at => {}
+            _ => return false,
+        }
+        self.watchdog_at = self.next_sample();
+        let pid = match self.worker.as_ref().and_then(|e| e.state().pid) {
+            Some(pid) => pid,
+            None => return false,
+        };
+        let triggered = match self.watchdog.as_mut().and_then(|e| e.sample(pid)) {
+            Some(triggered) => triggered,
+            None => return false,
+        };
+        let watchdog = &self.project.watchdog;
+        warn(format!(
+            "[{}] 资源占用连续 {} 次超出阈值: {}",
+            self.name,
+            watchdog.samples,
+            triggered.join("，")
+        ));
+        match watchdog.action {
+            WatchdogAction::LOG => {}
+            WatchdogAction::SCRIPT => {
+                let mut policy = self.script_policy.clone();
+                policy.envs.insert("MAINPID".to_string(), pid.to_string());
+                policy
+                    .envs
+                    .insert("WATCHDOG_REASON".to_string(), triggered.join("\n"));
+                match execute_script(
+                    "看门狗钩子",
+                    &self.project.script_worker,
+                    &watchdog.script,
+                    &policy,
+                ) {
+                    Ok(0) => {}
+                    _ => error(format!("[{}] 看门狗脚本执行失败。", self.name)),
+                }
+            }
+            WatchdogAction::SIGNAL => {
+                info(format!(
+                    "[{}] 向程序的进程组 {} 发送 {}.",
+                    self.name,
+                    pid,
+                    signal_name(watchdog.signal.0)
+                ));
+                signal_group(pid, watchdog.signal.0);
+            }
+            WatchdogAction::RESTART => {
+                // 与健康检查失败相同，计入启动次数限制
+                if self.record_start() {
+                    info(format!("[{}] 重启程序，原因: watchdog.", self.name));
+                    self.restart_now();
+                } else {
+                    self.fail();
+                }
+                return true;
+            }
+        }
+        false
+    }
+
+    /**
+    检查程序状态，处理健康检查、启动检查与退出后的重启策略
+     */
+    pub fn tick(&mut self) {
+        if self.state != RUNNING {
+            return;
+        }
+        if self.check_watch() || self.check_schedule() || self.check_watchdog() {
+            return;
+        }
+        if let Some(restart_at) = self.restart_at {
+            if Instant::now() >= restart_at {
+                self.restart_at = None;
+                self.restart_now();
+            }
+            return;
+        }
+        if let Some(health_check) = &self.health_check {
+            for x in health_check.get_status() {
+                if x == 0 {
+                    self.health_fail = 0;
+                } else {
+                    self.health_fail += 1;
+                }
+            }
+            if self.health_fail >= self.project.check_health.failures as i32 {
+                error(format!("[{}] 健康检查失败！主进程将重启。", self.name));
+                health_check.stop();
+                if self.record_start() {
+                    self.restart_now();
+                } else {
+                    self.fail();
+                }
+                return;
+            }
+        }
+        if let Some(started_check) = &self.started_check {
+            for x in started_check.get_status() {
+                if x == 0 {
+                    self.started_success += 1;
+                } else {
+                    self.started_success = 0;
+                }
+            }
+            if self.started_success >= self.project.check_started.success as i32
+                && self.started_success != -1
+            {
+                started_check.stop();
+                info(format!("[{}] 启动成功！回调脚本", self.name));
+                self.started = true;
+                self.attempts = 0;
+                let status = execute_script(
+                    "启动成功回调钩子",
+                    &self.project.script_worker,
+                    &self.project.check_started.started_script,
+                    &self.script_policy,
+                );
+                if let Ok(0) = status {
+                    info(format!("[{}] 启动检测回调执行完成。", self.name))
+                } else {
+                    error(format!("[{}] 启动检测回调执行失败。", self.name))
+                }
+                self.started_success = -1;
+            }
+        }
+        let state = self.worker.as_ref().unwrap().state();
+        if state.launch < self.launches || self.handled == state.launch {
+            // 状态属于之前的启动或已处理
+            return;
+        }
+        let (exit, class) = match state.action {
+            STARTED if self.started_check.is_none() => {
+                self.started = true;
+                return;
+            }
+            EXITED(exit) => (exit, classify_exit(&self.project, &exit)),
+            // 被 OOM Killer 结束视为失败，与收到 SIGKILL 退出相同
+            OOM_KILLED => (ExitInfo::signal(SIGKILL), FAILURE),
+            _ => return,
+        };
+        self.handled = state.launch;
+        self.last_exit = Some(exit);
+        self.last_class = Some(class);
+        if self.started_check.is_none() && class == SUCCESS {
+            self.started = true;
+        }
+        self.stop_check();
+        let max_delay = self.project.restart_backoff.max_delay as u64;
+        if self.launched_at.elapsed() >= Duration::from_secs(max_delay) {
+            // 运行时间足够长，不再视为连续重启
+            self.attempts = 0;
+        }
+        let policy = self.project.restart_policy;
+        if class == FATAL {
+            error(format!(
+                "[{}] 主进程已退出 ({})，属于不可恢复的错误，不再重启.",
+                self.name, exit
+            ));
+            self.worker.as_ref().unwrap().exit();
+            self.state = FINISHED;
+        } else if (class == SUCCESS && policy == FAIL) || policy == NONE {
+            debug(format!(
+                "[{}] 主进程已结束 ({})，根据策略,项目已结束.",
+                self.name, exit
+            ));
+            self.worker.as_ref().unwrap().exit();
+            self.state = FINISHED;
+        } else if state.action == OOM_KILLED {
+            info(format!(
+                "[{}] 主进程因内存超限被结束，根据策略,项目将重启.",
+                self.name
+            ));
+            self.schedule_restart();
+        } else if class == FAILURE {
+            debug(format!(
+                "[{}] 主进程异常退出 ({})，根据策略,项目将重启.",
+                self.name, exit
+            ));
+            self.schedule_restart();
+        } else {
+            debug(format!(
+                "[{}] 主进程已退出 ({})，根据策略,项目将重启.",
+                self.name, exit
+            ));
+            self.schedule_restart();
+        }
+    }
+
+    /**
+    应用重新加载后的配置并输出变化的内容，启动参数、环境变量、钩子脚本或执行环境变化时重启程序
+     */
+    pub fn update(&mut self, spec: ProgramSpec) {
+        let changed = changed_fields(&self.project, &spec.project);
+        let mut restart = false;
+        if self.context.args != spec.context.args {
+            info(format!(
+                "[{}] 启动参数变更: {} -> {}",
+                self.name,
+                log::mask(&format!("{:?}", self.context.args)),
+                log::mask(&format!("{:?}", spec.context.args))
+            ));
+            restart = true;
+        }
+        for line in env_diff(&self.policy.envs, &spec.policy.envs) {
+            info(format!(
+                "[{}] 环境变量变更: {}",
+                self.name,
+                log::mask(&line)
+            ));
+            restart = true;
+        }
+        if changed.is_empty().not() {
+            info(format!(
+                "[{}] 变更的配置项: {}",
+                self.name,
+                changed.join(", ")
+            ));
+        }
+        if changed.iter().any(|e| e == "cgroup") {
+            warn(format!(
+                "[{}] cgroup 配置的变更需要重启 args-tools 才能生效",
+                self.name
+            ));
+        }
+        restart |= changed.iter().any(|e| RESTART_FIELDS.contains(&e.as_str()));
+        let deferred = changed.iter().any(|e| {
+            RESTART_FIELDS.contains(&e.as_str()).not()
+                && LIVE_FIELDS.contains(&e.as_str()).not()
+                && e != "cgroup"
+        });
+        let cgroup = self.policy.cgroup.take();
+        self.depends_on = spec.depends_on;
+        self.project = spec.project;
+        self.context = spec.context;
+        self.policy = spec.policy;
+        self.policy.cgroup = cgroup;
+        self.script_policy = spec.script_policy;
+        if ["watch", "binary", "workdir"]
+            .iter()
+            .any(|e| changed.iter().any(|field| field == e))
+        {
+            self.watcher = self.create_watcher();
+        }
+        if changed.iter().any(|e| e == "restart_schedule") {
+            self.schedule_at = self.next_schedule();
+        }
+        if changed.iter().any(|e| e == "max_uptime") {
+            self.uptime_at = self.next_uptime();
+        }
+        if changed.iter().any(|e| e == "watchdog") {
+            self.watchdog = self.create_watchdog();
+            self.watchdog_at = self.next_sample().filter(|_| self.state == RUNNING);
+        }
+        if self.worker.is_none() {
+            return;
+        }
+        if restart && self.state == RUNNING {
+            info(format!("[{}] 配置已变更，重启程序.", self.name));
+            self.relaunch();
+        } else if restart || deferred {
+            info(format!("[{}] 配置的变更将在程序下次启动时生效.", self.name));
+            self.stale = true;
+        }
+    }
+
+    /// 停止当前的工作线程，以当前配置重新创建并启动程序
+    fn relaunch(&mut self) {
+        self.stop_workers();
+        self.worker = None;
+        self.health_check = None;
+        self.started_check = None;
+        self.launches = 0;
+        self.handled = 0;
+        self.restart_at = None;
+        self.stale = false;
+        self.spawn_workers();
+    }
+
+    /// 停止程序并等待全部工作线程退出
+    pub fn shutdown(&mut self) {
+        if self.worker.is_some() {
+            info(format!("[{}] 程序开始停止.", self.name));
+        }
+        self.stop_workers();
+        if let Some(cgroup) = &self.policy.cgroup {
+            cgroup.remove();
+        }
+    }
+
+    fn stop_workers(&self) {
+        if let Some(worker) = &self.worker {
+            worker.exit();
+            if let Some(x) = &self.health_check {
+                debug(format!("[{}] 等待健康检测脚本停止...", self.name));
+                x.close();
+                x.wait_closed();
+            }
+            if let Some(x) = &self.started_check {
+                debug(format!("[{}] 等待启动检测脚本停止...", self.name));
+                x.close();
+                x.wait_closed();
+            }
+            debug(format!("[{}] 等待主工作线程退出.", self.name));
+            worker.wait_exited();
+        }
+    }
+}
+
+/**
+按 `success_exit_codes`、`fatal_exit_codes` 与 `on_signal` 对程序的退出分类，`fatal_exit_codes` 优先
+ */
+fn classify_exit(project: &ProjectInfo, exit: &ExitInfo) -> ExitClass {
+    match exit.code {
+        None => project.on_signal,
+        Some(code) if project.fatal_exit_codes.contains(&code) => FATAL,
+        Some(code) if project.success_exit_codes.contains(&code) => SUCCESS,
+        Some(_) => FAILURE,
+    }
+}
+
+fn depend_state(state: ProgramState, started: bool, class: Option<ExitClass>) -> DependState {
+    match state {
+        FAILED => BROKEN,
+        FINISHED if class == Some(FATAL) => BROKEN,
+        _ if started => READY,
+        FINISHED if class == Some(SUCCESS) => READY,
+        FINISHED => BROKEN,
+        PENDING | RUNNING => WAITING,
+    }
+}
+
+fn file_state(path: &PathBuf) -> FileState {
+    fs::metadata(path)
+        .ok()
+        .map(|e| (e.len(), e.modified().unwrap_or(UNIX_EPOCH)))
+}
+
+/// 两份程序配置中值不同的顶层配置项
+fn changed_fields(old: &ProjectInfo, new: &ProjectInfo) -> Vec<String> {
+    let (old, new) = match (serde_json::to_value(old), serde_json::to_value(new)) {
+        (Ok(Value::Object(old)), Ok(Value::Object(new))) => (old, new),
+        _ => return vec![],
+    };
+    let mut fields: Vec<String> = new
+        .iter()
+        .filter(|(key, value)| old.get(*key) != Some(*value))
+        .map(|(key, _)| key.to_string())
+        .collect();
+    fields.sort();
+    fields
+}
+
+/// 环境变量的变化，新增为 `+KEY=VALUE`，删除为 `-KEY`，修改为 `KEY: OLD -> NEW`
+fn env_diff(old: &HashMap<String, String>, new: &HashMap<String, String>) -> Vec<String> {
+    let mut lines = vec![];
+    for (key, value) in new {
+        match old.get(key) {
+            None => lines.push(format!("+{}={}", key, value)),
+            Some(old_value) if old_value != value => {
+                lines.push(format!("{}: {} -> {}", key, old_value, value))
+            }
+            _ => {}
+        }
+    }
+    for key in old.keys().filter(|e| new.contains_key(*e).not()) {
+        lines.push(format!("-{}", key));
+    }
+    lines.sort();
+    lines
+}
+
+/**
+计算第 `attempts` 次连续重启的延时，`seed` 用于在 `jitter` 百分比范围内随机浮动
+ */
+fn backoff_delay(backoff: &RestartBackoff, attempts: u32, seed: u64) -> Duration {
+    let delay = (backoff.delay as u64)
+        .saturating_mul(1u64.checked_shl(attempts).unwrap_or(u64::MAX))
+        .min(backoff.max_delay as u64)
+        .saturating_mul(1000);
+    let jitter = delay * backoff.jitter.min(100) as u64 / 100;
+    if jitter == 0 {
+        return Duration::from_millis(delay);
+    }
+    Duration::from_millis(delay - jitter + seed % (jitter * 2 + 1))
+}
+
+/// `0` 到 `jitter` 之间的随机延时
+fn jitter_delay(jitter: Duration) -> Duration {
+    let millis = jitter.as_millis() as u64;
+    if millis == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_millis(random_seed() % (millis + 1))
+}
+
+fn random_seed() -> u64 {
+    let nanos = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .subsec_nanos() as u64;
+    // xorshift 打散时间的低位
+    let mut seed = nanos ^ 0x9E37_79B9_7F4A_7C15;
+    seed ^= seed << 13;
+    seed ^= seed >> 7;
+    seed ^= seed << 17;
+    seed
+}
+
+#[test]
+fn backoff_delay_test() {
+    let backoff = RestartBackoff {
+        delay: 1,
+        max_delay: 60,
+        jitter: 0,
+    };
+    assert_eq!(backoff_delay(&backoff, 0, 7), Duration::from_secs(1));
+    assert_eq!(backoff_delay(&backoff, 3, 7), Duration::from_secs(8));
+    assert_eq!(backoff_delay(&backoff, 6, 7), Duration::from_secs(60));
+    assert_eq!(backoff_delay(&backoff, 80, 7), Duration::from_secs(60));
+    let backoff = RestartBackoff {
+        delay: 10,
+        max_delay: 60,
+        jitter: 10,
+    };
+    for seed in 0..100 {
+        let delay = backoff_delay(&backoff, 0, seed * 997);
+        assert!(delay >= Duration::from_secs(9) && delay <= Duration::from_secs(11));
+    }
+    let backoff = RestartBackoff {
+        delay: 0,
+        max_delay: 60,
+        jitter: 10,
+    };
+    assert!(backoff_delay(&backoff, 5, 7).is_zero());
+}
+
+#[test]
+fn depend_state_test() {
+    assert_eq!(depend_state(PENDING, false, None), WAITING);
+    assert_eq!(depend_state(RUNNING, true, None), READY);
+    assert_eq!(depend_state(FINISHED, true, Some(FATAL)), BROKEN);
+    assert_eq!(depend_state(FINISHED, true, Some(SUCCESS)), READY);
+    assert_eq!(depend_state(FAILED, true, Some(FAILURE)), BROKEN);
+    // 启动检查通过前已结束
+    assert_eq!(depend_state(FINISHED, false, Some(SUCCESS)), READY);
+    assert_eq!(depend_state(FINISHED, false, Some(FATAL)), BROKEN);
+    assert_eq!(depend_state(FINISHED, false, Some(FAILURE)), BROKEN);
+    assert_eq!(depend_state(FAILED, false, Some(SUCCESS)), BROKEN);
+    assert_eq!(DependState::all([READY, READY]), READY);
+    assert_eq!(DependState::all([READY, WAITING]), WAITING);
+    assert_eq!(DependState::all([WAITING, BROKEN]), BROKEN);
+    assert_eq!(DependState::all([]), READY);
+}
+
+#[test]
+fn classify_exit_test() {
+    let project: ProjectInfo = serde_yaml::from_str("{name: app, binary: app.sh}").unwrap();
+    assert_eq!(classify_exit(&project, &ExitInfo::code(0)), SUCCESS);
+    assert_eq!(classify_exit(&project, &ExitInfo::code(78)), FAILURE);
+    assert_eq!(
+        classify_exit(&project, &ExitInfo::signal(libc::SIGTERM)),
+        FAILURE
+    );
+    let project: ProjectInfo = serde_yaml::from_str(
+        "{name: app, binary: app.sh, success_exit_codes: [0, 143], fatal_exit_codes: [78, 0], on_signal: SUCCESS}",
+    )
+    .unwrap();
+    assert_eq!(classify_exit(&project, &ExitInfo::code(143)), SUCCESS);
+    assert_eq!(classify_exit(&project, &ExitInfo::code(78)), FATAL);
+    assert_eq!(classify_exit(&project, &ExitInfo::code(0)), FATAL);
+    assert_eq!(classify_exit(&project, &ExitInfo::code(1)), FAILURE);
+    assert_eq!(
+        classify_exit(&project, &ExitInfo::signal(libc::SIGKILL)),
+        SUCCESS
+    );
+}
+
+#[test]
+fn config_diff_test() {
+    let old: ProjectInfo =
+        serde_yaml::from_str("{name: app, binary: app.sh, restart_policy: NONE}").unwrap();
+    let new: ProjectInfo =
+        serde_yaml::from_str("{name: app, binary: app.sh, nice: 5, before_script: 'exit 0'}")
+            .unwrap();
+    assert_eq!(
+        changed_fields(&old, &new),
+        vec!["before_script", "nice", "restart_policy"]
+    );
+    assert!(changed_fields(&old, &old).is_empty());
+    let old = HashMap::from([
+        ("A".to_string(), "1".to_string()),
+        ("B".to_string(), "2".to_string()),
+    ]);
+    let new = HashMap::from([
+        ("A".to_string(), "3".to_string()),
+        ("C".to_string(), "4".to_string()),
+    ]);
+    assert_eq!(env_diff(&old, &new), vec!["+C=4", "-B", "A: 1 -> 3"]);
+}
@@ -0,0 +1,155 @@
+/*
+ * Copyright (c) 2022, Dragon's Zone Project. All rights reserved.
+ *
+ * Permission is hereby granted, free of charge, to any person obtaining a copy
+ * of this software and associated documentation files (the "Software"), to deal
+ * in the Software without restriction, including without limitation the rights
+ * to use, copy, modify, merge, publish, distribute, sublicense, and/or sell
+ * copies of the Software, and to permit persons to whom the Software is
+ * furnished to do so, subject to the following conditions:
+ *
+ * The above copyright notice and this permission notice shall be included in
+ * all copies or substantial portions of the Software.
+ *
+ * THE SOFTWARE IS PROVIDED "AS IS", WITHOUT WARRANTY OF ANY KIND, EXPRESS OR
+ * IMPLIED, INCLUDING BUT NOT LIMITED TO THE WARRANTIES OF MERCHANTABILITY,
+ * FITNESS FOR A PARTICULAR PURPOSE AND NON-INFRINGEMENT. IN NO EVENT SHALL THE
+ * AUTHORS OR COPYRIGHT HOLDERS BE LIABLE FOR ANY CLAIM, DAMAGES OR OTHER
+ * LIABILITY, WHETHER IN AN ACTION OF CONTRACT, TORT OR OTHERWISE, ARISING FROM,
+ * OUT OF OR IN CONNECTION WITH THE SOFTWARE OR THE USE OR OTHER DEALINGS IN THE
+ * SOFTWARE.
+ */
+
+use std::time::Instant;
+
+use libc::pid_t;
+
+use crate::config::prop::{ByteSize, WatchdogConfig};
+use crate::utils::process::{clock_ticks, read_usage, ProcUsage};
+
+/**
+资源看门狗：采样程序主进程的资源占用，任一项连续 `samples` 次超出阈值时触发
+ */
+pub struct Watchdog {
+    config: WatchdogConfig,
+    /// 内存、CPU、文件描述符与线程数连续超出阈值的次数
+    exceeded: [u32; 4],
+    /// 上次采样的进程、CPU 时间与采样时间，用于计算 CPU 使用率
+    last_cpu: Option<(pid_t, u64, Instant)>,
+}
+
+impl Watchdog {
+    pub fn new(config: &WatchdogConfig) -> Self {
+        Watchdog {
+            config: config.clone(),
+            exceeded: [0; 4],
+            last_cpu: None,
+        }
+    }
+
+    /**
+    采样进程 `pid`，触发时返回超出阈值的项并重新计数；进程不存在时不计数
+     */
+    pub fn sample(&mut self, pid: pid_t) -> Option<Vec<String>> {
+        let usage = read_usage(pid)?;
+        let now = Instant::now();
+        let cpu = match self.last_cpu {
+            Some((last_pid, ticks, at)) if last_pid == pid => {
+                let elapsed = now.duration_since(at).as_secs_f64();
+                let used = usage.cpu_ticks.saturating_sub(ticks) as f64 / clock_ticks() as f64;
+                Some(used * 100.0 / elapsed.max(0.001))
+            }
+            _ => None,
+        };
+        self.last_cpu = Some((pid, usage.cpu_ticks, now));
+        self.check(&usage, cpu)
+    }
+
+    /// 程序重启后重新计数
+    pub fn reset(&mut self) {
+        self.exceeded = [0; 4];
+        self.last_cpu = None;
+    }
+
+    fn check(&mut self, usage: &ProcUsage, cpu: Option<f64>) -> Option<Vec<String>> {
+        let config = &self.config;
+        let mut items = vec![
+            (
+                0,
+                config
+                    .max_rss
+                    .filter(|e| usage.rss > e.0)
+                    .map(|e| format!("内存 {} 超过 {}", ByteSize(usage.rss / 1024 * 1024), e)),
+            ),
+            (
+                2,
+                config
+                    .max_fds
+                    .filter(|e| usage.fds > *e as u64)
+                    .map(|e| format!("文件描述符 {} 超过 {}", usage.fds, e)),
+            ),
+            (
+                3,
+                config
+                    .max_threads
+                    .filter(|e| usage.threads > *e as u64)
+                    .map(|e| format!("线程数 {} 超过 {}", usage.threads, e)),
+            ),
+        ];
+        // 首次采样无法计算 CPU 使用率，保持原计数
+        if let Some(cpu) = cpu {
+            let item = config
+                .max_cpu
+                .filter(|e| cpu > *e as f64)
+                .map(|e| format!("CPU {:.1}% 超过 {}%", cpu, e));
+            items.insert(1, (1, item));
+        }
+        let mut triggered = vec![];
+        for (index, item) in items {
+            match item {
+                Some(item) => {
+                    self.exceeded[index] += 1;
+                    if self.exceeded[index] >= config.samples.max(1) {
+                        triggered.push(item);
+                    }
+                }
+                None => self.exceeded[index] = 0,
+            }
+        }
+        if triggered.is_empty() {
+            return None;
+        }
+        self.exceeded = [0; 4];
+        Some(triggered)
+    }
+}
+
+#[test]
+fn watchdog_check_test() {
+    let config: WatchdogConfig =
+        serde_yaml::from_str("{samples: 2, max_rss: 1M, max_cpu: 50, max_fds: 10}").unwrap();
+    let mut watchdog = Watchdog::new(&config);
+    let usage = |rss: u64, fds: u64| ProcUsage {
+        rss,
+        cpu_ticks: 0,
+        threads: 1,
+        fds,
+    };
+    assert_eq!(watchdog.check(&usage(2 << 20, 5), None), None);
+    // 中间一次未超出时重新计数
+    assert_eq!(watchdog.check(&usage(1 << 20, 5), Some(10.0)), None);
+    assert_eq!(watchdog.check(&usage(2 << 20, 5), Some(80.0)), None);
+    assert_eq!(
+        watchdog.check(&usage(2 << 20, 20), Some(90.0)),
+        Some(vec![
+            "内存 2M 超过 1M".to_string(),
+            "CPU 90.0% 超过 50%".to_string()
+        ])
+    );
+    // 触发后全部重新计数
+    assert_eq!(watchdog.check(&usage(2 << 20, 20), None), None);
+    assert_eq!(
+        watchdog.check(&usage(0, 20), None),
+        Some(vec!["文件描述符 20 超过 10".to_string()])
+    );
+}